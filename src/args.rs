@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use crate::input::parse_position;
+use crate::options::WrapMode;
+
+pub const USAGE: &str = "usage: te [options] [+line[:col]] [file[:line[:col]]]...
+
+  file            file to open, a file that does not exist yet is created on save
+  -               read the text of a scratch buffer from stdin
+  +line[:col]     put the cursor at a line (and column) of the next file
+  -R, --readonly  open files without allowing edits
+  -w, --wait      exit once the files are closed, with status 1 if one was not saved,
+                  for use as $EDITOR
+  --wrap[=word]   wrap long lines at any character, or at word boundaries
+  -V, --version   print the version
+  -h, --help      print this help";
+
+pub enum Action {
+    Edit(Args),
+    Help,
+    Version,
+}
+
+#[derive(Default)]
+pub struct Args {
+    // files to open with the 0-based (row, col) to put the cursor at, `-` is stdin
+    pub files: Vec<(String, Option<(usize, usize)>)>,
+    pub readonly: bool,
+    pub wait: bool,
+    pub wrap: Option<WrapMode>,
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Action, String> {
+    // parses the command line arguments without the program name
+    let mut parsed = Args::default();
+    let mut position = None;
+    let mut is_option = true;
+    for arg in args {
+        if is_option && arg.starts_with('-') && arg != "-" {
+            match arg.as_str() {
+                "--" => is_option = false,
+                "-h" | "--help" => return Ok(Action::Help),
+                "-V" | "--version" => return Ok(Action::Version),
+                "-R" | "--readonly" => parsed.readonly = true,
+                "-w" | "--wait" => parsed.wait = true,
+                "--wrap" | "--wrap=char" => parsed.wrap = Some(WrapMode::Char),
+                "--wrap=word" => parsed.wrap = Some(WrapMode::Word),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        } else if let Some(line) = arg.strip_prefix('+').filter(|_| is_option) {
+            position = Some(parse_position(line).ok_or(format!("invalid line {}", arg))?);
+        } else {
+            let (file, file_position) = split_position(&arg);
+            parsed.files.push((file, position.take().or(file_position)));
+        }
+    }
+    Ok(Action::Edit(parsed))
+}
+
+fn split_position(arg: &str) -> (String, Option<(usize, usize)>) {
+    // `file:line` and `file:line:col` as printed by compilers and grep, unless a file has that name
+    if !Path::new(arg).exists() {
+        for (i, _) in arg.match_indices(':') {
+            if let Some(position) = parse_position(&arg[i + 1..]).filter(|_| i > 0) {
+                return (arg[..i].to_string(), Some(position));
+            }
+        }
+    }
+    (arg.to_string(), None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn files(args: &[&str]) -> Vec<(String, Option<(usize, usize)>)> {
+        match parse(args.iter().map(|arg| arg.to_string())) {
+            Ok(Action::Edit(args)) => args.files,
+            _ => panic!("expected files to edit"),
+        }
+    }
+
+    #[test]
+    fn parses_files_and_positions() {
+        assert_eq!(
+            files(&["a.rs", "+42", "b.rs", "c.rs:3:7", "-", "--", "-R"]),
+            vec![
+                ("a.rs".to_string(), None),
+                ("b.rs".to_string(), Some((41, 0))),
+                ("c.rs".to_string(), Some((2, 6))),
+                ("-".to_string(), None),
+                ("-R".to_string(), None),
+            ]
+        );
+        assert!(matches!(parse(["--help".to_string()]), Ok(Action::Help)));
+        assert!(parse(["--nope".to_string()]).is_err());
+        assert!(parse(["+x".to_string()]).is_err());
+    }
+}
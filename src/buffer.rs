@@ -1,307 +1,1122 @@
 use crate::cursor::Cursor;
+use crate::highlight::{self, Highlighter, State};
+use crate::history::{Edit, EditKind, History};
+use crate::indent::Indent;
+use crate::line::{CharClass, Line, Wrap};
+use crate::line_ending::LineEnding;
+use crate::options::{LineNumbers, Options, WrapMode};
+use crate::replace::Replace;
+use crate::style::{Span, Style};
 use crate::terminal::Terminal;
-use std::fs::File;
-use std::io;
-use std::io::{BufRead, LineWriter, Write};
+use regex::Regex;
+use ropey::Rope;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::{fs, io, process};
 
-struct Line {
-    value: String,
+pub struct Buffer {
+    // stores the entire file as a rope, exactly as it is on disk including line endings
+    // provides facility for editing and saving the file content
+    text: Rope,
+    // position of the cursor in the text as (line, grapheme column)
+    row: usize,
+    col: usize,
+    // display column vertical movement aims for, kept while moving through shorter lines
+    desired_x: Option<usize>,
+    // position of the cursor on the terminal, updated while rendering
+    cursor: Cursor,
+    top_offset: usize,
+    // wrapped rows of the line at `top_offset` scrolled off the top, when that line is taller than the screen
+    top_skip: usize,
+    // first display column shown when lines are not wrapped
+    left_offset: usize,
+    filename: String,
+    history: History,
+    // line ending written for new lines, and whether the file uses both kinds
+    line_ending: LineEnding,
+    is_mixed: bool,
+    // one level of indentation as used by the file
+    indent: Indent,
+    // the file does not exist on disk yet, it is created on the first save
+    is_new: bool,
+    // the buffer has edits that are not saved yet
+    is_dirty: bool,
+    // the buffer was saved at least once since it was opened
+    is_saved: bool,
+    options: Options,
+    // text highlighted while searching, empty when not searching
+    search: String,
+    // char range of the match a replacement is waiting to be confirmed for
+    current_match: Option<(usize, usize)>,
+    // the other end of the selection, the cursor is always at one end of it
+    anchor: Option<(usize, usize)>,
+    highlighter: Option<Box<dyn Highlighter>>,
+    // highlight state at the start of each line, only known up to the last line highlighted since an edit
+    states: Vec<State>,
 }
 
-impl Line {
-    pub fn from(value: String) -> Self {
-        Line { value }
+impl Buffer {
+    pub fn new(filename: &str, options: Options) -> io::Result<Self> {
+        // opens `filename`, a path that does not exist yet gives an empty buffer
+        let (text, is_new) = match File::open(filename) {
+            Ok(file) => (Rope::from_reader(io::BufReader::new(file))?, false),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (Rope::new(), true),
+            Err(err) => return Err(err),
+        };
+        Ok(Self::from_text(filename, text, is_new, options))
     }
 
-    pub fn display_rows(&self, is_wrap: bool) -> usize {
-        if !is_wrap {
-            return 1;
-        }
+    pub fn scratch(text: &str, options: Options) -> Self {
+        // a buffer that is not backed by a file, like text piped into the editor
+        Self::from_text("", Rope::from_str(text), false, options)
+    }
 
-        if self.value.is_empty() {
-            1
-        } else {
-            let col = Terminal::cols();
-            let mut div = self.value.len() / col;
-            if self.value.len() % col != 0 {
-                div += 1;
-            }
-            div
+    fn from_text(filename: &str, text: Rope, is_new: bool, options: Options) -> Self {
+        let (line_ending, is_mixed) = LineEnding::detect(&text);
+        let indent = Indent::detect(&text, options.tab_width);
+        let first_line: Cow<str> = text.line(0).into();
+        let highlighter = highlight::detect(filename, &first_line);
+
+        Buffer {
+            text,
+            row: 0,
+            col: 0,
+            desired_x: None,
+            cursor: Cursor::new(),
+            top_offset: 0,
+            top_skip: 0,
+            left_offset: 0,
+            filename: filename.to_string(),
+            history: History::default(),
+            line_ending,
+            is_mixed,
+            indent,
+            is_new,
+            is_dirty: false,
+            is_saved: false,
+            options,
+            search: String::new(),
+            current_match: None,
+            anchor: None,
+            highlighter,
+            states: vec![State::Normal],
         }
     }
 
-    pub fn render(&mut self) {
-        // renders the content of `self.value`, can take multiple terminal rows due to wrapping
-        let col = Terminal::cols();
-        for i in 0..self.display_rows(true) {
-            print!(
-                "{}\r\n",
-                self.value[(i * col)..(((i + 1) * col).min(self.value.len()))].to_string()
-            );
-        }
+    fn line(&self, row: usize) -> Line {
+        Line::from(self.text.line(row), self.options.tab_width)
     }
 
-    pub fn render_no_wrap(&mut self, cursor_col_pos: usize) {
-        let col = Terminal::cols();
-        if cursor_col_pos > col {
-            let left_offset = (cursor_col_pos - col).min(self.value.len());
-            let right_offset = (left_offset + col).min(self.value.len());
-            print!("{}\r\n", self.value[left_offset..right_offset].to_string());
-        } else {
-            print!(
-                "{}\r\n",
-                self.value[..(col).min(self.value.len())].to_string()
-            );
-        }
+    fn line_count(&self) -> usize {
+        self.text.len_lines()
     }
-}
 
-pub struct Buffer {
-    // stores the entire file as a vector of `Line`s
-    // provides facility for editing and saving the file content
-    lines: Vec<Line>,
-    cursor: Cursor,
-    top_offset: usize,
-    filename: String,
-    is_wrap: bool,
-}
+    fn char_index(&self, row: usize, byte: usize) -> usize {
+        // converts a byte offset inside line `row` to a char index in `self.text`
+        self.text.byte_to_char(self.text.line_to_byte(row) + byte)
+    }
 
-impl Buffer {
-    pub fn new(filename: &str) -> Result<Self, ()> {
-        if let Ok(file) = File::open(filename) {
-            let mut buffer: Vec<Line> = vec![];
+    fn text_rows() -> usize {
+        // terminal rows available for text, the last row is used by the status bar
+        Terminal::rows() - 1
+    }
 
-            for line in io::BufReader::new(file).lines() {
-                let line = line.expect("failed reading line");
-                buffer.push(Line::from(line));
-            }
+    pub fn filename(&self) -> &str {
+        self.filename.as_str()
+    }
 
-            Ok(Buffer {
-                lines: buffer,
-                cursor: Cursor::new(),
-                top_offset: 0,
-                filename: filename.to_string(),
-                is_wrap: false,
-            })
-        } else {
-            Err(())
+    pub fn is_scratch(&self) -> bool {
+        self.filename.is_empty()
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        self.options.readonly
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.is_dirty
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.is_saved
+    }
+
+    pub fn file_type(&self) -> Option<&'static str> {
+        self.highlighter
+            .as_ref()
+            .map(|highlighter| highlighter.name())
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    pub fn missing_directory(&self) -> Option<&Path> {
+        // the parent directory of the file if it has to be created before saving
+        Path::new(&self.filename)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty() && !parent.exists())
+    }
+
+    pub fn create_directory(&self) -> io::Result<()> {
+        match self.missing_directory() {
+            Some(directory) => fs::create_dir_all(directory),
+            None => Ok(()),
         }
     }
 
-    pub fn filename(&self) -> &str {
-        self.filename.as_str()
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
     }
 
     pub fn buffer_row(&self) -> usize {
-        // Maps the current cursor row in terminal window with the row in `self.lines`
-        let cursor_row = self.cursor.row();
-        let mut buffer_row = self.top_offset;
+        self.row
+    }
+
+    pub fn columns(&self) -> (usize, usize) {
+        // byte offset and display column of the cursor in its line, they differ after tabs or non-ASCII text
+        let line = self.line(self.row);
+        (line.byte_offset(self.col), line.x(self.col))
+    }
 
-        let mut total_cursor_rows = 0;
+    pub fn position(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
 
-        for i in self.top_offset..self.lines.len() {
-            total_cursor_rows += self.lines[i].display_rows(self.is_wrap);
-            if total_cursor_rows > cursor_row {
-                buffer_row = i;
-                break;
+    pub fn goto(&mut self, (row, col): (usize, usize)) {
+        self.start_move();
+        self.row = row.min(self.line_count() - 1);
+        self.col = col.min(self.line(self.row).len());
+    }
+
+    pub fn set_search(&mut self, query: &str) {
+        self.search = query.to_string();
+    }
+
+    pub fn find(&self, query: &str, from: (usize, usize), forward: bool) -> Option<(usize, usize)> {
+        // finds the closest match of `query` at or after `from`, or before `from` when searching
+        // backwards, wrapping around the end of the file
+        if query.is_empty() {
+            return None;
+        }
+        let (row, col) = from;
+        let start = self.line(row).byte_offset(col);
+        let count = self.line_count();
+
+        for i in 0..=count {
+            let row = if forward {
+                (row + i) % count
+            } else {
+                (row + count - i % count) % count
+            };
+            let text: Cow<str> = self.text.line(row).into();
+            let found = match (i, forward) {
+                (0, true) => text[start..].find(query).map(|byte| byte + start),
+                (0, false) => text
+                    .match_indices(query)
+                    .map(|(byte, _)| byte)
+                    .take_while(|&byte| byte < start)
+                    .last(),
+                (_, true) => text.find(query),
+                (_, false) => text.rfind(query),
+            };
+            if let Some(byte) = found {
+                return Some((row, self.line(row).col_at_byte(byte)));
             }
         }
+        None
+    }
+
+    fn start_move(&mut self) {
+        // a cursor movement ends the current undo step and drops the selection
+        self.history.seal();
+        self.anchor = None;
+        self.desired_x = None;
+    }
+
+    fn start_vertical_move(&mut self) -> usize {
+        // like `start_move`, but returns the display column from before the current run of vertical moves
+        let desired_x = self.desired_x;
+        self.start_move();
+        let x = desired_x.unwrap_or_else(|| self.line(self.row).position(self.col, self.wrap()).1);
+        self.desired_x = Some(x);
+        x
+    }
+
+    pub fn select(&mut self, movement: impl FnOnce(&mut Self)) {
+        // runs a cursor movement that extends the selection instead of dropping it
+        let anchor = self.anchor.unwrap_or((self.row, self.col));
+        movement(self);
+        self.anchor = Some(anchor);
+    }
+
+    fn cursor_index(&self) -> usize {
+        // char index of the cursor in `self.text`
+        self.char_index(self.row, self.line(self.row).byte_offset(self.col))
+    }
 
-        buffer_row
+    fn selection(&self) -> Option<(usize, usize)> {
+        // char range of the selection, `None` when nothing is selected
+        let (row, col) = self.anchor?;
+        let anchor = self.char_index(row, self.line(row).byte_offset(col));
+        let cursor = self.cursor_index();
+        (anchor != cursor).then(|| (anchor.min(cursor), anchor.max(cursor)))
     }
 
-    pub fn buffer_row_start(&self, buffer_row: usize) -> usize {
-        // Finds the starting cursor row in terminal for the given row in buffer
-        self.lines[self.top_offset..buffer_row]
-            .iter()
-            .map(|line| line.display_rows(self.is_wrap))
-            .sum()
+    pub fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        Some(self.text.slice(start..end).to_string())
     }
 
-    pub fn buffer_col(&self) -> usize {
-        // Maps the current cursor column in terminal window with the column in `self.lines[self.buffer_row()]`
+    pub fn delete_selection(&mut self) -> bool {
+        // removes the selected text as its own undo step, false when nothing is selected
+        // an empty selection is dropped too, every edit ends the selection
+        let selection = self.selection();
+        self.anchor = None;
+        let Some((start, end)) = selection else {
+            return false;
+        };
+        self.history.seal();
+        let before = (self.row, self.col);
+        let edit = self.remove(start, end);
+        self.goto_char(start);
+        self.record(edit, EditKind::Other, before);
+        self.history.seal();
+        true
+    }
 
-        let cursor_row = self.cursor.row();
-        let cursor_col = self.cursor.col();
+    pub fn paste(&mut self, text: &str) {
+        // inserts `text` at the cursor as a single undo step, its lines use the buffer's line ending
+        self.delete_selection();
+        let text = text
+            .replace("\r\n", "\n")
+            .replace('\n', self.line_ending.as_str());
+        if text.is_empty() {
+            return;
+        }
+        self.history.seal();
+        let before = (self.row, self.col);
+        let index = self.cursor_index();
+        self.text.insert(index, &text);
+        self.goto_char(index + text.chars().count());
+        self.record(Edit::Insert { index, text }, EditKind::Other, before);
+        self.history.seal();
+    }
+
+    fn range_span(
+        &self,
+        row: usize,
+        line: &Line,
+        range: (usize, usize),
+        style: Style,
+    ) -> Option<Span> {
+        // the part of a char range of the text that is on line `row`
+        let (start, end) = range;
+        let line_start = self.text.line_to_char(row);
+        let line_end = line_start + self.text.line(row).len_chars();
+        let is_empty_here = start == end && (line_start..line_end).contains(&start);
+        let overlaps = start < line_end && end > line_start;
+        if !overlaps && !is_empty_here {
+            return None;
+        }
+        let line_byte = self.text.line_to_byte(row);
+        let start = self.text.char_to_byte(start.max(line_start)) - line_byte;
+        let mut end = self.text.char_to_byte(end.min(line_end)) - line_byte;
+        if is_empty_here {
+            // an empty range highlights the grapheme it is in front of
+            end = line.byte_offset(line.col_at_byte(start) + 1);
+        }
+        Some(Span { start, end, style })
+    }
+
+    fn syntax_spans(&mut self, row: usize, line: &Line) -> Vec<Span> {
+        // highlights line `row`, first catching up the states of the lines above it
+        let Some(highlighter) = &self.highlighter else {
+            return vec![];
+        };
+        while self.states.len() <= row {
+            let previous = self.states.len() - 1;
+            let state = highlighter.highlight(
+                self.line(previous).value(),
+                self.states[previous],
+                &mut vec![],
+            );
+            self.states.push(state);
+        }
+        let mut spans = vec![];
+        highlighter.highlight(line.value(), self.states[row], &mut spans);
+        spans
+    }
 
-        let buffer_row = self.buffer_row();
-        let cursor_row_offset = cursor_row - self.buffer_row_start(buffer_row);
+    fn invalidate_states(&mut self, index: usize) {
+        // an edit at char `index` can change the highlight state of every line after it
+        let row = self.text.char_to_line(index.min(self.text.len_chars()));
+        self.states.truncate(row + 1);
+    }
 
-        let col = Terminal::cols();
+    fn spans(&self, row: usize, line: &Line) -> Vec<Span> {
+        // styles for the parts of line `row` that are highlighted
+        let mut spans = vec![];
+        if let Some(selection) = self.selection() {
+            spans.extend(self.range_span(row, line, selection, Style::Selection));
+        }
+        if !self.search.is_empty() {
+            let cursor = (row == self.row).then(|| line.byte_offset(self.col));
+            spans.extend(
+                line.value()
+                    .match_indices(self.search.as_str())
+                    .map(|(start, found)| Span {
+                        start,
+                        end: start + found.len(),
+                        style: if Some(start) == cursor {
+                            Style::CurrentMatch
+                        } else {
+                            Style::Match
+                        },
+                    }),
+            );
+        }
+        if let Some(current_match) = self.current_match {
+            spans.extend(self.range_span(row, line, current_match, Style::CurrentMatch));
+        }
+        spans
+    }
 
-        (col * cursor_row_offset) + cursor_col
+    fn goto_char(&mut self, index: usize) {
+        // moves the cursor to a char index of the text without ending the current undo step
+        self.row = self.text.char_to_line(index);
+        let byte = self.text.char_to_byte(index) - self.text.line_to_byte(self.row);
+        self.col = self.line(self.row).col_at_byte(byte);
     }
 
-    pub fn last_cursor_row(&self) -> usize {
-        // Calculates the last row on terminal for all the lines
-        self.lines[self.top_offset..]
-            .iter()
-            .map(|line| line.display_rows(self.is_wrap))
-            .sum()
+    pub fn start_replace(&mut self, regex: Regex, replacement: String) -> Replace {
+        // a replace session over the selection or the whole file, it is a single undo step
+        self.history.seal();
+        let text = self.text.to_string();
+        match self.selection() {
+            Some(selection) => Replace::new(regex, replacement, text, selection, selection.0),
+            None => Replace::new(
+                regex,
+                replacement,
+                text,
+                (0, self.text.len_chars()),
+                self.cursor_index(),
+            ),
+        }
     }
 
-    pub fn last_cursor_col(&self, buffer_row: usize) -> usize {
-        // Calculates the last column for the cursor for given row
-        let line_length = self.lines[buffer_row].value.len();
+    pub fn next_replace(&mut self, replace: &mut Replace) -> bool {
+        // moves the cursor to the next match of the session, false when there are none left
+        loop {
+            // stepping over an empty match at the end of the pass leaves `next` past its limit
+            let found = if replace.next > replace.limit() {
+                None
+            } else {
+                let from = self.text.char_to_byte(replace.next);
+                let limit = self.text.char_to_byte(replace.limit());
+                // the wrapped pass stops before `origin`, a match there was found by the first pass
+                replace.expand(from).filter(|&(start, end, _)| {
+                    end <= limit && !(replace.is_wrapped && start >= limit)
+                })
+            };
+            if let Some((start, end, _)) = found {
+                let range = (self.text.byte_to_char(start), self.text.byte_to_char(end));
+                replace.current = Some(range);
+                self.current_match = Some(range);
+                self.goto_char(range.0);
+                return true;
+            }
+            if replace.is_wrapped {
+                replace.current = None;
+                self.current_match = None;
+                return false;
+            }
+            replace.is_wrapped = true;
+            replace.next = replace.scope_start;
+        }
+    }
 
-        if !self.is_wrap {
-            return line_length;
+    fn replace_range(&mut self, start: usize, end: usize, replacement: &str) {
+        let before = (self.row, self.col);
+        if start < end {
+            let edit = self.remove(start, end);
+            self.record(edit, EditKind::Replace, before);
+        }
+        if !replacement.is_empty() {
+            self.text.insert(start, replacement);
+            self.record(
+                Edit::Insert {
+                    index: start,
+                    text: replacement.to_string(),
+                },
+                EditKind::Replace,
+                before,
+            );
         }
+    }
 
-        let col = Terminal::cols();
+    pub fn replace_current(&mut self, replace: &mut Replace) {
+        // replaces the match waiting for confirmation and continues after the replacement
+        let Some((start, end)) = replace.current else {
+            return;
+        };
+        let Some((start_byte, end_byte, replacement)) =
+            replace.expand(self.text.char_to_byte(start))
+        else {
+            return;
+        };
+        self.replace_range(start, end, &replacement);
+        replace.splice(start_byte, end_byte, &replacement);
 
-        line_length % col
+        let inserted = replacement.chars().count();
+        replace.shift(end - start, inserted);
+        replace.next = start + inserted + usize::from(start == end);
+        replace.count += 1;
+    }
+
+    pub fn skip_current(&mut self, replace: &mut Replace) {
+        if let Some((start, end)) = replace.current {
+            replace.next = end + usize::from(start == end);
+        }
+    }
+
+    pub fn replace_all(&mut self, replace: &mut Replace) {
+        // replaces the current match and every match after it in the session at once
+        let Some((start, _)) = replace.current else {
+            return;
+        };
+        // (from, limit, is_wrapped) of each pass, the wrapped pass stops before `origin`
+        let mut passes = vec![(start, replace.limit(), replace.is_wrapped)];
+        if !replace.is_wrapped {
+            passes.push((replace.scope_start, replace.origin, true));
+        }
+
+        let mut matches = vec![];
+        for (from, limit, is_wrapped) in passes {
+            let mut from = self.text.char_to_byte(from);
+            let limit = self.text.char_to_byte(limit);
+            while from <= limit {
+                let Some(found) = replace.expand(from) else {
+                    break;
+                };
+                if found.1 > limit || (is_wrapped && found.0 >= limit) {
+                    break;
+                }
+                from = if found.0 == found.1 {
+                    // step over an empty match so it isn't found again
+                    match replace.text()[found.1..].chars().next() {
+                        Some(char) => found.1 + char.len_utf8(),
+                        None => limit + 1,
+                    }
+                } else {
+                    found.1
+                };
+                matches.push(found);
+            }
+        }
+
+        // replace from the end of the file so the offsets of earlier matches stay valid,
+        // the session ends here so its copy of the text is left as it is
+        matches.sort_by_key(|&(start, _, _)| Reverse(start));
+        for (start, end, replacement) in &matches {
+            let (start, end) = (self.text.byte_to_char(*start), self.text.byte_to_char(*end));
+            self.replace_range(start, end, replacement);
+            self.goto_char(start);
+        }
+        replace.count += matches.len();
+        replace.current = None;
+        self.current_match = None;
+    }
+
+    pub fn finish_replace(&mut self) {
+        self.current_match = None;
+        self.history.seal();
     }
 
     pub fn write(&mut self, char: char) {
-        self.adjust_cursor_boundary_before_edit();
-        let row = self.buffer_row();
-        let col = self.buffer_col();
-        match char {
+        // typing over a selection replaces it
+        self.delete_selection();
+        let before = (self.row, self.col);
+        let line = self.line(self.row);
+        let byte = line.byte_offset(self.col);
+        let index = self.char_index(self.row, byte);
+        let (text, kind) = match char {
             '\n' => {
-                let line = &self.lines[row].value;
-                if col >= line.len() {
-                    self.lines.insert(row + 1, Line::from(String::new()));
-                    self.cursor.new_line();
-                } else {
-                    let cur = &line[..col];
-                    let next = &line[col..];
-                    let result = next.to_string();
-                    self.lines[row].value = cur.to_string();
-                    self.lines.insert(row + 1, Line::from(result));
-                    self.cursor.new_line();
-                }
+                // every line split is an undo step of its own
+                self.history.seal();
+                let text =
+                    self.line_ending.as_str().to_string() + &self.new_line_indent(&line, byte);
+                self.text.insert(index, &text);
+                self.row += 1;
+                self.col = self
+                    .line(self.row)
+                    .col_at_byte(text.len() - self.line_ending.as_str().len());
+                (text, EditKind::Other)
             }
             _ => {
-                let line = &self.lines[row].value;
-                if col > line.len() {
-                    self.lines[row].value.push(char);
-                } else {
-                    let pre = &line[..col];
-                    let post = &line[col..];
-                    let mut result = pre.to_string();
-                    result.push(char);
-                    result.push_str(post);
-                    self.lines[row].value = result;
-                    self.cursor.right();
-                }
+                self.text.insert_char(index, char);
+                // a combining character merges with the previous grapheme instead of adding a column
+                self.col = self.line(self.row).col_at_byte(byte + char.len_utf8());
+                (char.to_string(), EditKind::Typing)
             }
-        }
+        };
+        self.record(Edit::Insert { index, text }, kind, before);
     }
 
-    fn adjust_cursor_boundary_before_edit(&mut self) {
-        // If cursor is not on text, bring it back to text to avoid out of bounds
-        let last_cursor_row = self.last_cursor_row();
-        if self.cursor.row() > last_cursor_row {
-            let last_cursor_column = self.last_cursor_col(self.lines.len() - 1);
-            self.cursor.goto(last_cursor_row, last_cursor_column);
+    fn new_line_indent(&self, line: &Line, byte: usize) -> String {
+        // leading whitespace for a line split from `line` at `byte`
+        if !self.options.auto_indent {
+            return String::new();
         }
-        let row = self.buffer_row();
-        let col = self.buffer_col();
-
-        if col >= self.lines[row].value.len() {
-            let last_cursor_column = self.last_cursor_col(row);
-            self.cursor.goto(self.cursor.row(), last_cursor_column);
+        let before = &line.value()[..byte];
+        let mut indent: String = before
+            .chars()
+            .take_while(|char| *char == ' ' || *char == '\t')
+            .collect();
+        if self.options.smart_indent && before.trim_end().ends_with(['{', '(', '[', ':']) {
+            indent.push_str(&self.indent.unit());
         }
+        indent
+    }
+
+    fn record(&mut self, edit: Edit, kind: EditKind, before: (usize, usize)) {
+        // adds an edit that was just applied to the history, the cursor is already moved
+        self.invalidate_states(edit.index());
+        self.anchor = None;
+        self.desired_x = None;
+        self.history
+            .record(edit, kind, before, (self.row, self.col));
+        self.is_dirty = true;
+    }
+
+    fn remove(&mut self, start: usize, end: usize) -> Edit {
+        // removes the chars in `start..end` and returns the edit for the history
+        let text = self.text.slice(start..end).to_string();
+        self.text.remove(start..end);
+        Edit::Remove { index: start, text }
     }
 
     pub fn delete(&mut self) {
-        self.adjust_cursor_boundary_before_edit();
-        let row = self.buffer_row();
-        let col = self.buffer_col();
-        if col == 0 {
-            if row != 0 {
-                let l = self.lines[row - 1].value.len();
-
-                let current_line = self.lines[row].value.clone();
-                self.lines[row - 1].value.push_str(&*current_line);
-
-                self.cursor.delete_line(l);
-                self.lines.remove(row);
+        // Backspace, erases the grapheme before the cursor or joins the line with the previous one
+        let target = if self.col > 0 {
+            let line = self.line(self.row);
+            let end = line.byte_offset(self.col);
+            // in leading spaces, erase back to the previous indentation level
+            match self.indent {
+                Indent::Spaces(width) if line.value()[..end].bytes().all(|byte| byte == b' ') => {
+                    (self.row, (self.col - 1) / width * width)
+                }
+                _ => (self.row, self.col - 1),
             }
+        } else if self.row > 0 {
+            (self.row - 1, self.line(self.row - 1).len())
+        } else {
+            (self.row, self.col)
+        };
+        self.delete_to(target);
+    }
+
+    pub fn delete_forward(&mut self) {
+        // Delete, erases the grapheme under the cursor or joins the next line into this one
+        let target = if self.col < self.line(self.row).len() {
+            (self.row, self.col + 1)
+        } else if self.row + 1 < self.line_count() {
+            (self.row + 1, 0)
         } else {
-            let line = &self.lines[row].value;
-            let cur = &line[..col];
-            let next = &line[col..];
-            let mut result = cur.to_string();
-            result.pop();
-            result.push_str(next);
-            self.lines[row].value = result;
-            self.cursor.left();
+            (self.row, self.col)
+        };
+        self.delete_to(target);
+    }
+
+    pub fn undo(&mut self) {
+        if let Some((kind, (row, col), index)) = self.history.undo(&mut self.text) {
+            self.invalidate_states(index);
+            self.anchor = None;
+            self.desired_x = None;
+            self.row = row;
+            self.col = col;
+            self.is_dirty = true;
+            if kind == EditKind::LineEnding {
+                (self.line_ending, self.is_mixed) = LineEnding::detect(&self.text);
+            }
         }
     }
 
-    pub fn down(&mut self) {
-        if self.cursor.row() >= self.last_cursor_row() - 1 {
-            return;
+    pub fn redo(&mut self) {
+        if let Some((kind, (row, col), index)) = self.history.redo(&mut self.text) {
+            self.invalidate_states(index);
+            self.anchor = None;
+            self.desired_x = None;
+            self.row = row;
+            self.col = col;
+            self.is_dirty = true;
+            if kind == EditKind::LineEnding {
+                (self.line_ending, self.is_mixed) = LineEnding::detect(&self.text);
+            }
         }
-        if self.cursor.row() + 2 >= Terminal::rows() {
-            self.top_offset += 1;
+    }
+
+    pub fn line_ending_label(&self) -> String {
+        // line ending style and final newline state shown in the status bar
+        let mut label = if self.is_mixed {
+            "Mixed".to_string()
         } else {
-            self.cursor.down();
+            self.line_ending.label().to_string()
+        };
+        if self.text.len_chars() > 0 && !self.has_final_newline() {
+            label.push_str(" noeol");
+        }
+        label
+    }
+
+    fn has_final_newline(&self) -> bool {
+        let len = self.text.len_chars();
+        len > 0 && self.text.char(len - 1) == '\n'
+    }
+
+    pub fn convert_line_endings(&mut self, line_ending: LineEnding) {
+        // rewrites every line ending in the file as `line_ending` in a single undo step
+        self.history.seal();
+        let position = (self.row, self.col);
+        for row in 0..self.line_count() {
+            let line = self.text.line(row);
+            let current = match LineEnding::of(line) {
+                Some(current) if current != line_ending => current,
+                _ => continue,
+            };
+            let end = self.text.line_to_char(row) + line.len_chars();
+            let start = end - current.as_str().len();
+
+            let edit = self.remove(start, end);
+            self.record(edit, EditKind::LineEnding, position);
+            self.text.insert(start, line_ending.as_str());
+            self.record(
+                Edit::Insert {
+                    index: start,
+                    text: line_ending.as_str().to_string(),
+                },
+                EditKind::LineEnding,
+                position,
+            );
+        }
+        self.history.seal();
+        self.line_ending = line_ending;
+        self.is_mixed = false;
+    }
+
+    pub fn toggle_line_endings(&mut self) {
+        match self.line_ending {
+            LineEnding::Crlf if !self.is_mixed => self.convert_line_endings(LineEnding::Lf),
+            LineEnding::Lf if !self.is_mixed => self.convert_line_endings(LineEnding::Crlf),
+            line_ending => self.convert_line_endings(line_ending),
+        }
+    }
+
+    pub fn down(&mut self) {
+        let x = self.start_vertical_move();
+        let line = self.line(self.row);
+        let wrap = self.wrap();
+        let (row, _) = line.position(self.col, wrap);
+        if row + 1 < line.display_rows(wrap) {
+            self.col = line.col_at(row + 1, x, wrap);
+        } else if self.row + 1 < self.line_count() {
+            self.row += 1;
+            self.col = self.line(self.row).col_at(0, x, wrap);
         }
     }
 
     pub fn up(&mut self) {
-        if self.cursor.row() == 0 && self.top_offset > 0 {
-            self.top_offset -= 1;
-        } else if self.cursor.row() > 0 {
-            self.cursor.up();
+        let x = self.start_vertical_move();
+        let line = self.line(self.row);
+        let wrap = self.wrap();
+        let (row, _) = line.position(self.col, wrap);
+        if row > 0 {
+            self.col = line.col_at(row - 1, x, wrap);
+        } else if self.row > 0 {
+            self.row -= 1;
+            let line = self.line(self.row);
+            let last_row = line.display_rows(wrap) - 1;
+            self.col = line.col_at(last_row, x, wrap);
         }
     }
 
     pub fn left(&mut self) {
-        self.cursor.left();
+        self.start_move();
+        if self.col > 0 {
+            self.col -= 1;
+        }
     }
 
     pub fn right(&mut self) {
-        self.cursor.right();
+        self.start_move();
+        if self.col < self.line(self.row).len() {
+            self.col += 1;
+        }
+    }
+
+    pub fn home(&mut self) {
+        // goes to the first non-blank grapheme of the line, or to the start of the line when already there
+        self.start_move();
+        let line = self.line(self.row);
+        let word_chars = &self.options.word_chars;
+        let first = (0..line.len())
+            .find(|&col| line.class(col, word_chars) != CharClass::Space)
+            .unwrap_or(line.len());
+        self.col = if self.col == first { 0 } else { first };
     }
 
-    pub fn save(&self) -> std::io::Result<()> {
-        let file = File::create(&self.filename).expect("could not open file in write only mode");
-        let mut file = LineWriter::new(file);
+    pub fn end(&mut self) {
+        self.start_move();
+        self.col = self.line(self.row).len();
+    }
+
+    pub fn file_start(&mut self) {
+        self.goto((0, 0));
+    }
+
+    pub fn file_end(&mut self) {
+        let row = self.line_count() - 1;
+        self.goto((row, self.line(row).len()));
+    }
+
+    pub fn page_up(&mut self) {
+        // scrolls up a screenful of lines and moves the cursor with the text
+        let rows = Self::text_rows();
+        self.top_offset = self.top_offset.saturating_sub(rows);
+        self.move_rows(self.row.saturating_sub(rows));
+    }
+
+    pub fn page_down(&mut self) {
+        let rows = Self::text_rows();
+        let last = self.line_count() - 1;
+        self.top_offset = (self.top_offset + rows).min(last);
+        self.move_rows((self.row + rows).min(last));
+    }
+
+    fn move_rows(&mut self, row: usize) {
+        // goes to line `row` keeping the display column of the cursor
+        let x = self.start_vertical_move();
+        self.row = row;
+        self.col = self.line(row).col_at(0, x, self.wrap());
+    }
+
+    fn word_left_position(&self) -> (usize, usize) {
+        // start of the word before the cursor, or the end of the previous line at the start of a line
+        if self.col == 0 {
+            return match self.row {
+                0 => (0, 0),
+                row => (row - 1, self.line(row - 1).len()),
+            };
+        }
+        let line = self.line(self.row);
+        let word_chars = &self.options.word_chars;
+        let mut col = self.col;
+        while col > 0 && line.class(col - 1, word_chars) == CharClass::Space {
+            col -= 1;
+        }
+        if col > 0 {
+            let class = line.class(col - 1, word_chars);
+            while col > 0 && line.class(col - 1, word_chars) == class {
+                col -= 1;
+            }
+        }
+        (self.row, col)
+    }
+
+    fn word_right_position(&self) -> (usize, usize) {
+        // end of the word after the cursor, or the start of the next line at the end of a line
+        let line = self.line(self.row);
+        if self.col == line.len() {
+            return if self.row + 1 < self.line_count() {
+                (self.row + 1, 0)
+            } else {
+                (self.row, self.col)
+            };
+        }
+        let word_chars = &self.options.word_chars;
+        let mut col = self.col;
+        while col < line.len() && line.class(col, word_chars) == CharClass::Space {
+            col += 1;
+        }
+        if col < line.len() {
+            let class = line.class(col, word_chars);
+            while col < line.len() && line.class(col, word_chars) == class {
+                col += 1;
+            }
+        }
+        (self.row, col)
+    }
+
+    pub fn word_left(&mut self) {
+        self.start_move();
+        (self.row, self.col) = self.word_left_position();
+    }
 
-        for line in &self.lines {
-            file.write_all(line.value.as_ref())?;
-            file.write_all(b"\r\n")?;
+    pub fn word_right(&mut self) {
+        self.start_move();
+        (self.row, self.col) = self.word_right_position();
+    }
+
+    fn delete_to(&mut self, (row, col): (usize, usize)) {
+        // removes the text between the cursor and (row, col), deleting the selection instead if there is one
+        // the cursor ends at the start of the removed text, a whole line ending is removed when joining lines
+        if self.delete_selection() {
+            return;
+        }
+        let before = (self.row, self.col);
+        let cursor = self.cursor_index();
+        let target = self.char_index(row, self.line(row).byte_offset(col));
+        let (start, end) = (cursor.min(target), cursor.max(target));
+        if start == end {
+            return;
+        }
+        let edit = self.remove(start, end);
+        self.goto_char(start);
+        self.record(edit, EditKind::Deleting, before);
+    }
+
+    pub fn delete_word_backward(&mut self) {
+        self.delete_to(self.word_left_position());
+    }
+
+    pub fn delete_word_forward(&mut self) {
+        self.delete_to(self.word_right_position());
+    }
+
+    pub fn save(&mut self) -> std::io::Result<()> {
+        // writes a temporary file next to the target and renames it over the target,
+        // so a failed save leaves the original file untouched
+        let path = Path::new(&self.filename);
+        // save through symlinks instead of replacing them with a regular file
+        let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let directory = target
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let file_name = target
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let temp = directory.join(format!(".{}.{}.tmp", file_name, process::id()));
+
+        let result = self.write_temp(&temp, &target).and_then(|()| {
+            if self.options.backup && target.exists() {
+                Self::backup(&target)?;
+            }
+            fs::rename(&temp, &target)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result?;
+
+        // make the rename itself durable, not every platform can sync a directory
+        if let Ok(directory) = File::open(&directory) {
+            let _ = directory.sync_all();
         }
 
-        file.flush()?;
+        self.is_new = false;
+        self.is_dirty = false;
+        self.is_saved = true;
+        Ok(())
+    }
+
+    fn write_temp(&self, temp: &Path, target: &Path) -> io::Result<()> {
+        let file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+        if let Ok(metadata) = fs::metadata(target) {
+            file.set_permissions(metadata.permissions())?;
+        }
+
+        let mut writer = BufWriter::new(file);
+        for chunk in self.text.chunks() {
+            writer.write_all(chunk.as_bytes())?;
+        }
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()
+    }
+
+    fn backup(target: &Path) -> io::Result<()> {
+        // keeps the current contents of `target` as `<target>~`
+        let mut backup = target.as_os_str().to_owned();
+        backup.push("~");
+        let backup = PathBuf::from(backup);
+
+        match fs::remove_file(&backup) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        // the old file survives the rename through the hard link, copy where links aren't supported
+        if fs::hard_link(target, &backup).is_err() {
+            fs::copy(target, &backup)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_filename(&mut self, filename: &str) {
+        // saves go to `filename` from now on, its name can change how the text is highlighted
+        let first_line: Cow<str> = self.text.line(0).into();
+        self.highlighter = highlight::detect(filename, &first_line);
+        self.states = vec![State::Normal];
+        self.filename = filename.to_string();
+        self.is_new = !Path::new(filename).exists();
+    }
+
+    pub fn set(&mut self, setting: &str) -> Result<(), String> {
+        self.options.set(setting)?;
+        self.desired_x = None;
         Ok(())
     }
 
     pub fn toggle_wrapping(&mut self) {
-        self.is_wrap = !self.is_wrap;
+        self.desired_x = None;
+        self.options.wrap = match self.options.wrap {
+            WrapMode::Off => WrapMode::Char,
+            WrapMode::Char => WrapMode::Word,
+            WrapMode::Word => WrapMode::Off,
+        };
     }
 
-    pub fn render(&mut self) {
-        let row = Terminal::rows();
-        let mut rows_to_draw: usize = self.top_offset + row - 1;
-
-        if self.is_wrap {
-            let mut cur = self.top_offset;
-            let mut console_rows = 0_usize;
-            for i in self.top_offset..self.lines.len() {
-                console_rows += self.lines[i].display_rows(self.is_wrap);
-                if console_rows < row {
-                    cur = i;
-                } else {
-                    break;
-                }
-            }
-            rows_to_draw = cur + 1;
+    pub fn cycle_line_numbers(&mut self) {
+        self.options.line_numbers = match self.options.line_numbers {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Hybrid,
+            LineNumbers::Hybrid => LineNumbers::Off,
+        };
+    }
+
+    fn gutter_width(&self) -> usize {
+        // digits of the largest line number and a space before the text
+        if self.options.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+        self.line_count().to_string().len().max(3) + 1
+    }
+
+    fn gutter(&self, row: usize) -> String {
+        let number = match self.options.line_numbers {
+            LineNumbers::Off => return String::new(),
+            LineNumbers::Absolute => row + 1,
+            LineNumbers::Hybrid if row == self.row => row + 1,
+            LineNumbers::Relative | LineNumbers::Hybrid => row.abs_diff(self.row),
+        };
+        format!("{:>1$} ", number, self.gutter_width() - 1)
+    }
+
+    fn text_cols(&self) -> usize {
+        // display columns left for the text next to the gutter
+        Terminal::cols().saturating_sub(self.gutter_width()).max(1)
+    }
+
+    fn wrap(&self) -> Option<Wrap> {
+        let is_word = match self.options.wrap {
+            WrapMode::Off => return None,
+            WrapMode::Char => false,
+            WrapMode::Word => true,
+        };
+        Some(Wrap {
+            width: self.text_cols(),
+            is_word,
+            is_indented: self.options.wrap_indent,
+        })
+    }
+
+    fn scroll_horizontally(&mut self, x: usize) {
+        // moves `left_offset` so display column `x` stays at least the side margin away from the edges,
+        // the margin is never below one column so the cursor does not sit on a cut off marker
+        let width = self.text_cols();
+        let margin = self.options.side_margin.max(1).min((width - 1) / 2);
+        if x < self.left_offset + margin {
+            self.left_offset = x.saturating_sub(margin);
+        }
+        if x + margin >= self.left_offset + width {
+            self.left_offset = x + margin + 1 - width;
+        }
+    }
+
+    fn scroll(&mut self) {
+        // moves `top_offset` so the cursor is visible and places the terminal cursor on it
+        let text_rows = Self::text_rows();
+        if self.row < self.top_offset {
+            self.top_offset = self.row;
+        }
+        if self.row - self.top_offset > text_rows {
+            // every line takes at least one row, no need to measure lines that can't be on screen
+            self.top_offset = self.row - text_rows;
         }
 
-        if rows_to_draw > self.lines.len() {
-            rows_to_draw = self.lines.len();
+        if self.row != self.top_offset {
+            self.top_skip = 0;
         }
 
-        for line in &mut self.lines[self.top_offset..rows_to_draw] {
-            if self.is_wrap {
-                line.render();
+        let wrap = self.wrap();
+        let (row, x) = self.line(self.row).position(self.col, wrap);
+        let mut cursor_row: usize = (self.top_offset..self.row)
+            .map(|i| self.line(i).display_rows(wrap))
+            .sum::<usize>()
+            + row;
+        while cursor_row >= text_rows && self.top_offset < self.row {
+            cursor_row -= self.line(self.top_offset).display_rows(wrap);
+            self.top_offset += 1;
+        }
+        if self.top_offset == self.row {
+            // the cursor line is at the top, scroll through it by rows when it is taller than the screen
+            self.top_skip = self
+                .top_skip
+                .min(row)
+                .max((row + 1).saturating_sub(text_rows));
+            cursor_row -= self.top_skip;
+        }
+
+        let cursor_col = match wrap {
+            Some(wrap) => {
+                self.left_offset = 0;
+                x.min(wrap.width - 1)
+            }
+            None => {
+                self.scroll_horizontally(x);
+                x - self.left_offset
+            }
+        };
+        self.cursor
+            .goto(cursor_row, self.gutter_width() + cursor_col);
+    }
+
+    pub fn render(&mut self) {
+        self.scroll();
+
+        let text_rows = Self::text_rows();
+        let width = self.text_cols();
+        let wrap = self.wrap();
+        let mut console_rows = 0_usize;
+
+        for i in self.top_offset..self.line_count() {
+            if console_rows >= text_rows {
+                break;
+            }
+            let line = self.line(i);
+            let mut spans = self.syntax_spans(i, &line);
+            spans.extend(self.spans(i, &line));
+            let gutter = self.gutter(i);
+            if let Some(wrap) = wrap {
+                // the last line on screen is cut off at the bottom instead of left out
+                let skip = if i == self.top_offset {
+                    self.top_skip
+                } else {
+                    0
+                };
+                let end = line
+                    .display_rows(Some(wrap))
+                    .min(skip + text_rows - console_rows);
+                line.render(wrap, &gutter, &spans, skip..end);
+                console_rows += end - skip;
             } else {
-                line.render_no_wrap(self.cursor.col() + 1);
+                console_rows += 1;
+                line.render_no_wrap(self.left_offset, width, &gutter, &spans);
             }
         }
     }
@@ -321,14 +1136,16 @@ mod test {
         let mut f = File::create(filename).unwrap();
         f.write_all(b", World").unwrap();
 
-        let mut buffer = Buffer::new(filename).unwrap();
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
         buffer.write('H');
         buffer.write('e');
         buffer.write('l');
         buffer.write('l');
         buffer.write('o');
+        assert!(!buffer.is_saved());
 
         buffer.save().unwrap();
+        assert!(buffer.is_saved());
 
         let mut file = File::open(filename).unwrap();
         assert_eq!(file.read_line().unwrap().unwrap(), "Hello, World");
@@ -339,7 +1156,7 @@ mod test {
         let mut file = File::open(filename).unwrap();
         let mut result = String::new();
         file.read_to_string(&mut result).unwrap();
-        assert_eq!(result, "Hello\r\n, World\r\n");
+        assert_eq!(result, "Hello\n, World");
 
         buffer.delete();
         buffer.delete();
@@ -352,4 +1169,555 @@ mod test {
         assert_eq!(file.read_line().unwrap().unwrap(), "H, World");
         remove_file(filename).unwrap();
     }
+
+    #[test]
+    fn buffer_edits_grapheme_clusters() {
+        let filename = "unicode_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all("日本e\u{301}".as_bytes()).unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.right();
+        buffer.right();
+        buffer.right();
+        assert_eq!(buffer.position().1, 3);
+        buffer.write('é');
+        buffer.left();
+        buffer.delete();
+        buffer.down();
+        buffer.up();
+        buffer.write('\n');
+
+        buffer.save().unwrap();
+        let mut file = File::open(filename).unwrap();
+        let mut result = String::new();
+        file.read_to_string(&mut result).unwrap();
+        assert_eq!(result, "日本\né");
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_deletes_forward_and_joins_lines() {
+        let filename = "delete_forward_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all("ab\r\ne\u{301}".as_bytes()).unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.delete_forward();
+        assert_eq!(buffer.text.to_string(), "b\r\ne\u{301}");
+        buffer.end();
+        buffer.delete_forward();
+        assert_eq!(buffer.text.to_string(), "be\u{301}");
+        assert_eq!(buffer.position(), (0, 1));
+        buffer.delete_forward();
+        buffer.delete_forward();
+        assert_eq!(buffer.text.to_string(), "b");
+
+        buffer.undo();
+        assert_eq!(buffer.text.to_string(), "b\r\ne\u{301}");
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_undoes_and_redoes_edit_groups() {
+        let filename = "undo_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"ab").unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.right();
+        buffer.write('x');
+        buffer.write('y');
+        buffer.write('\n');
+        buffer.write('z');
+        buffer.delete();
+        buffer.delete();
+
+        buffer.undo();
+        assert_eq!(buffer.position(), (1, 1));
+        buffer.undo();
+        assert_eq!(buffer.position(), (1, 0));
+        buffer.undo();
+        assert_eq!(buffer.position(), (0, 3));
+        buffer.undo();
+        assert_eq!(buffer.position(), (0, 1));
+        assert_eq!(buffer.text.to_string(), "ab");
+
+        buffer.redo();
+        buffer.redo();
+        assert_eq!(buffer.text.to_string(), "axy\nb");
+        assert_eq!(buffer.position(), (1, 0));
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_preserves_and_converts_line_endings() {
+        let filename = "line_ending_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"a\r\nb\nc\r\n").unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        assert_eq!(buffer.line_ending_label(), "Mixed");
+        buffer.right();
+        buffer.write('\n');
+        buffer.save().unwrap();
+        let mut result = String::new();
+        File::open(filename)
+            .unwrap()
+            .read_to_string(&mut result)
+            .unwrap();
+        assert_eq!(result, "a\r\n\r\nb\nc\r\n");
+
+        buffer.convert_line_endings(LineEnding::Lf);
+        assert_eq!(buffer.text.to_string(), "a\n\nb\nc\n");
+        assert_eq!(buffer.line_ending_label(), "LF");
+        buffer.undo();
+        assert_eq!(buffer.text.to_string(), "a\r\n\r\nb\nc\r\n");
+        assert_eq!(buffer.line_ending_label(), "Mixed");
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_creates_new_files_on_save() {
+        let filename = "new_test_directory/new_test_file.txt";
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        assert!(buffer.is_new());
+        assert_eq!(
+            buffer.missing_directory(),
+            Some(Path::new("new_test_directory"))
+        );
+        buffer.delete();
+        buffer.write('a');
+
+        buffer.create_directory().unwrap();
+        assert!(buffer.is_dirty());
+        buffer.save().unwrap();
+        assert!(!buffer.is_new());
+        assert!(!buffer.is_dirty());
+        let mut result = String::new();
+        File::open(filename)
+            .unwrap()
+            .read_to_string(&mut result)
+            .unwrap();
+        assert_eq!(result, "a");
+        fs::remove_dir_all("new_test_directory").unwrap();
+
+        let filename = "empty_test_file.txt";
+        File::create(filename).unwrap();
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.down();
+        buffer.right();
+        buffer.delete();
+        buffer.write('b');
+        assert_eq!(buffer.text.to_string(), "b");
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_saves_atomically_with_backup() {
+        let filename = "backup_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"old").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(filename, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let options = Options {
+            backup: true,
+            ..Options::default()
+        };
+        let mut buffer = Buffer::new(filename, options).unwrap();
+        buffer.write('n');
+        buffer.save().unwrap();
+
+        let mut result = String::new();
+        File::open(filename)
+            .unwrap()
+            .read_to_string(&mut result)
+            .unwrap();
+        assert_eq!(result, "nold");
+        let mut backup = String::new();
+        File::open("backup_test_file.txt~")
+            .unwrap()
+            .read_to_string(&mut backup)
+            .unwrap();
+        assert_eq!(backup, "old");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(filename).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+        assert!(!Path::new(&format!(".{}.{}.tmp", filename, process::id())).exists());
+        remove_file(filename).unwrap();
+        remove_file("backup_test_file.txt~").unwrap();
+    }
+
+    #[test]
+    fn buffer_finds_matches_wrapping_around() {
+        let filename = "search_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all("foo bar\nbär foo\nbaz".as_bytes()).unwrap();
+
+        let buffer = Buffer::new(filename, Options::default()).unwrap();
+        assert_eq!(buffer.find("foo", (0, 0), true), Some((0, 0)));
+        assert_eq!(buffer.find("foo", (0, 1), true), Some((1, 4)));
+        assert_eq!(buffer.find("foo", (1, 5), true), Some((0, 0)));
+        assert_eq!(buffer.find("foo", (1, 4), false), Some((0, 0)));
+        assert_eq!(buffer.find("foo", (0, 0), false), Some((1, 4)));
+        assert_eq!(buffer.find("baz", (2, 0), false), Some((2, 0)));
+        assert_eq!(buffer.find("qux", (0, 0), true), None);
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_replaces_regex_matches_as_one_undo_step() {
+        let filename = "replace_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"a1 b2\na3 c4").unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.goto((0, 3));
+        let regex = Regex::new(r"([a-z])(\d)").unwrap();
+        let mut replace = buffer.start_replace(regex, "$2$1".to_string());
+
+        assert!(buffer.next_replace(&mut replace));
+        assert_eq!(buffer.position(), (0, 3));
+        buffer.replace_current(&mut replace);
+        assert!(buffer.next_replace(&mut replace));
+        assert_eq!(buffer.position(), (1, 0));
+        buffer.skip_current(&mut replace);
+        assert!(buffer.next_replace(&mut replace));
+        assert_eq!(buffer.position(), (1, 3));
+        buffer.replace_all(&mut replace);
+        buffer.finish_replace();
+        assert_eq!(replace.count, 3);
+        assert_eq!(buffer.text.to_string(), "1a 2b\na3 4c");
+
+        buffer.undo();
+        assert_eq!(buffer.text.to_string(), "a1 b2\na3 c4");
+
+        // an empty match at the cursor is replaced once, not again when the search wraps around
+        buffer.goto((0, 0));
+        let regex = Regex::new("(?m)^").unwrap();
+        let mut replace = buffer.start_replace(regex.clone(), "> ".to_string());
+        assert!(buffer.next_replace(&mut replace));
+        buffer.replace_all(&mut replace);
+        buffer.finish_replace();
+        assert_eq!(replace.count, 2);
+        assert_eq!(buffer.text.to_string(), "> a1 b2\n> a3 c4");
+
+        buffer.undo();
+        let mut replace = buffer.start_replace(regex, "> ".to_string());
+        while buffer.next_replace(&mut replace) {
+            buffer.replace_current(&mut replace);
+        }
+        buffer.finish_replace();
+        assert_eq!(replace.count, 2);
+        assert_eq!(buffer.text.to_string(), "> a1 b2\n> a3 c4");
+        remove_file(filename).unwrap();
+
+        // an empty match at the end of the file is the last one, whether it is replaced or skipped
+        for is_replaced in [true, false] {
+            let mut buffer = Buffer::scratch("a1\nb2", Options::default());
+            let regex = crate::replace::compile("$").unwrap();
+            let mut replace = buffer.start_replace(regex, ";".to_string());
+            while buffer.next_replace(&mut replace) {
+                if is_replaced {
+                    buffer.replace_current(&mut replace);
+                } else {
+                    buffer.skip_current(&mut replace);
+                }
+            }
+            buffer.finish_replace();
+            let expected = if is_replaced { "a1;\nb2;" } else { "a1\nb2" };
+            assert_eq!(buffer.text.to_string(), expected);
+        }
+
+        // patterns match per line, also in CRLF files
+        let mut buffer = Buffer::scratch("a1\r\nb2\r\n", Options::default());
+        let regex = crate::replace::compile(r"\d$").unwrap();
+        let mut replace = buffer.start_replace(regex, "#".to_string());
+        assert!(buffer.next_replace(&mut replace));
+        buffer.replace_all(&mut replace);
+        assert_eq!(buffer.text.to_string(), "a#\r\nb#\r\n");
+    }
+
+    #[test]
+    fn buffer_selects_cuts_and_pastes_lines() {
+        let filename = "selection_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"one\r\ntwo\r\n").unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.right();
+        buffer.select(Buffer::down);
+        buffer.select(Buffer::right);
+        assert_eq!(buffer.selected_text().unwrap(), "ne\r\ntw");
+
+        let text = buffer.selected_text().unwrap();
+        buffer.delete_selection();
+        assert_eq!(buffer.text.to_string(), "oo\r\n");
+        assert_eq!(buffer.position(), (0, 1));
+
+        buffer.right();
+        buffer.paste(&text.replace("\r\n", "\n"));
+        assert_eq!(buffer.text.to_string(), "oone\r\ntw\r\n");
+        assert_eq!(buffer.position(), (1, 2));
+        assert_eq!(buffer.selected_text(), None);
+
+        buffer.undo();
+        assert_eq!(buffer.text.to_string(), "oo\r\n");
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_drops_selection_on_edits() {
+        // an empty selection must not turn the next typed char into a selection
+        let mut buffer = Buffer::scratch("xyz", Options::default());
+        buffer.select(Buffer::right);
+        buffer.select(Buffer::left);
+        buffer.write('a');
+        buffer.write('b');
+        buffer.write('c');
+        assert_eq!(buffer.text.to_string(), "abcxyz");
+
+        // undoing the lines a selection started on must not leave it pointing past the end
+        let mut buffer = Buffer::scratch("", Options::default());
+        buffer.write('\n');
+        buffer.write('\n');
+        buffer.select(Buffer::up);
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.selected_text(), None);
+        assert_eq!(buffer.text.to_string(), "");
+    }
+
+    #[test]
+    fn buffer_moves_and_deletes_by_word() {
+        let filename = "word_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"let snake_case = a.b;\nnext").unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.word_right();
+        buffer.word_right();
+        assert_eq!(buffer.position(), (0, 14));
+        buffer.word_right();
+        buffer.word_right();
+        buffer.word_right();
+        buffer.word_right();
+        buffer.word_right();
+        buffer.word_right();
+        assert_eq!(buffer.position(), (1, 0));
+        buffer.word_left();
+        assert_eq!(buffer.position(), (0, 21));
+        buffer.word_left();
+        assert_eq!(buffer.position(), (0, 20));
+
+        buffer.goto((0, 14));
+        buffer.delete_word_backward();
+        assert_eq!(buffer.text.line(0).to_string(), "let  = a.b;\n");
+        buffer.delete_word_forward();
+        assert_eq!(buffer.text.line(0).to_string(), "let  a.b;\n");
+        buffer.goto((0, 10));
+        buffer.delete_word_forward();
+        assert_eq!(buffer.text.to_string(), "let  a.b;next");
+
+        buffer.undo();
+        assert_eq!(buffer.text.to_string(), "let  a.b;\nnext");
+        buffer.undo();
+        assert_eq!(buffer.text.to_string(), "let snake_case = a.b;\nnext");
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_remembers_column_on_vertical_moves() {
+        let filename = "column_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all("a long line\nab\n\nanother line".as_bytes())
+            .unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.goto((0, 8));
+        buffer.down();
+        assert_eq!(buffer.position(), (1, 2));
+        buffer.down();
+        assert_eq!(buffer.position(), (2, 0));
+        buffer.down();
+        assert_eq!(buffer.position(), (3, 8));
+        buffer.up();
+        buffer.up();
+        buffer.up();
+        assert_eq!(buffer.position(), (0, 8));
+
+        buffer.down();
+        buffer.left();
+        buffer.up();
+        assert_eq!(buffer.position(), (0, 1));
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_navigates_lines_and_pages() {
+        // sized from the terminal so a page down never reaches the end of the file
+        let rows = Buffer::text_rows();
+        let lines = rows * 3;
+        let filename = "navigation_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all("  indented\n".repeat(lines).as_bytes())
+            .unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.goto((0, 5));
+        buffer.home();
+        assert_eq!(buffer.position(), (0, 2));
+        buffer.home();
+        assert_eq!(buffer.position(), (0, 0));
+        buffer.end();
+        assert_eq!(buffer.position(), (0, 10));
+
+        buffer.page_down();
+        assert_eq!((buffer.top_offset, buffer.position()), (rows, (rows, 10)));
+        buffer.page_up();
+        assert_eq!((buffer.top_offset, buffer.position()), (0, (0, 10)));
+
+        buffer.file_end();
+        assert_eq!(buffer.position(), (lines, 0));
+        buffer.file_start();
+        assert_eq!(buffer.position(), (0, 0));
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_indents_new_lines() {
+        let filename = "indent_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"fn main() {\n  let a;\n").unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        buffer.goto((0, 11));
+        buffer.write('\n');
+        assert_eq!(buffer.position(), (1, 2));
+        buffer.write('\n');
+        assert_eq!(buffer.text.line(2).to_string(), "  \n");
+
+        buffer.write(' ');
+        buffer.write(' ');
+        buffer.delete();
+        assert_eq!(buffer.position(), (2, 2));
+        buffer.delete();
+        assert_eq!(buffer.position(), (2, 0));
+
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.text.to_string(), "fn main() {\n  \n  \n  let a;\n");
+        remove_file(filename).unwrap();
+
+        let mut buffer = Buffer::scratch("key:", Options::default());
+        let unit = buffer.indent.unit().len();
+        buffer.end();
+        buffer.write('\n');
+        assert_eq!(buffer.position(), (1, unit));
+        buffer.set("nosmartindent").unwrap();
+        buffer.write('(');
+        buffer.write('\n');
+        assert_eq!(buffer.position(), (2, unit));
+    }
+
+    #[test]
+    fn buffer_numbers_lines_in_gutter() {
+        let filename = "gutter_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"a\nb\nc\n").unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        assert_eq!(buffer.gutter(0), "");
+        buffer.goto((1, 0));
+        buffer.cycle_line_numbers();
+        assert_eq!(buffer.gutter(0), "  1 ");
+        buffer.cycle_line_numbers();
+        assert_eq!(
+            (buffer.gutter(0), buffer.gutter(1)),
+            ("  1 ".to_string(), "  0 ".to_string())
+        );
+        buffer.cycle_line_numbers();
+        assert_eq!(
+            (buffer.gutter(0), buffer.gutter(1)),
+            ("  1 ".to_string(), "  2 ".to_string())
+        );
+        assert_eq!(buffer.text_cols(), Terminal::cols() - 4);
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_scrolls_long_lines_with_margin() {
+        // sized from the terminal so the line is always longer than the screen
+        let width = Terminal::cols();
+        let filename = "scroll_test_file.txt";
+        let mut f = File::create(filename).unwrap();
+        f.write_all("x".repeat(width * 3).as_bytes()).unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        assert_eq!(buffer.text_cols(), width);
+        buffer.goto((0, width * 2));
+        buffer.scroll();
+        assert_eq!(buffer.left_offset, width * 2 + 5 + 1 - width);
+        let left_offset = buffer.left_offset;
+
+        buffer.goto((0, width * 2 - 10));
+        buffer.scroll();
+        assert_eq!(buffer.left_offset, left_offset);
+
+        buffer.goto((0, left_offset + 2));
+        buffer.scroll();
+        assert_eq!(buffer.left_offset, left_offset - 3);
+        remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn buffer_scrolls_through_lines_taller_than_the_screen() {
+        let width = Terminal::cols();
+        let text_rows = Buffer::text_rows();
+        let text = format!("{}\nshort", "x".repeat(width * (text_rows + 10)));
+        let mut buffer = Buffer::scratch(&text, Options::default());
+        buffer.toggle_wrapping();
+
+        buffer.goto((0, width * (text_rows + 5)));
+        buffer.scroll();
+        assert_eq!(buffer.top_offset, 0);
+        assert_eq!(buffer.top_skip, 6);
+
+        buffer.goto((0, width * 2));
+        buffer.scroll();
+        assert_eq!(buffer.top_skip, 2);
+
+        buffer.goto((1, 0));
+        buffer.scroll();
+        assert_eq!((buffer.top_offset, buffer.top_skip), (1, 0));
+    }
+
+    #[test]
+    fn buffer_updates_highlighting_after_edits() {
+        let filename = "highlight_test_file.rs";
+        let mut f = File::create(filename).unwrap();
+        f.write_all(b"/*\nfn\n").unwrap();
+
+        let mut buffer = Buffer::new(filename, Options::default()).unwrap();
+        assert_eq!(buffer.file_type(), Some("Rust"));
+        let line = buffer.line(1);
+        assert_eq!(buffer.syntax_spans(1, &line)[0].style, Style::Comment);
+
+        buffer.goto((0, 2));
+        buffer.delete();
+        let line = buffer.line(1);
+        assert_eq!(buffer.syntax_spans(1, &line)[0].style, Style::Keyword);
+
+        buffer.undo();
+        let line = buffer.line(1);
+        assert_eq!(buffer.syntax_spans(1, &line)[0].style, Style::Comment);
+        remove_file(filename).unwrap();
+    }
 }
@@ -0,0 +1,188 @@
+use std::env;
+use std::io::{self, stdout, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+pub trait Provider {
+    // a way to reach the clipboard of the desktop the editor is shown on
+    fn copy(&self, text: &str) -> io::Result<()>;
+
+    // `None` when the provider can only write to the clipboard
+    fn paste(&self) -> io::Result<Option<String>>;
+}
+
+pub struct Osc52;
+
+impl Provider for Osc52 {
+    fn copy(&self, text: &str) -> io::Result<()> {
+        // the terminal emulator sets its clipboard, this works over ssh as well
+        let mut stdout = stdout();
+        write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+        stdout.flush()
+    }
+
+    fn paste(&self) -> io::Result<Option<String>> {
+        // most terminals refuse to report their clipboard, pasting uses the internal register
+        Ok(None)
+    }
+}
+
+pub struct Tool {
+    // a command line program like `xclip` that reads and writes the clipboard
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+}
+
+impl Provider for Tool {
+    fn copy(&self, text: &str) -> io::Result<()> {
+        let mut child = Command::new(self.copy[0])
+            .args(&self.copy[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("{} failed", self.copy[0])))
+        }
+    }
+
+    fn paste(&self) -> io::Result<Option<String>> {
+        let output = Command::new(self.paste[0])
+            .args(&self.paste[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if output.status.success() {
+            Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+        } else {
+            Err(io::Error::other(format!("{} failed", self.paste[0])))
+        }
+    }
+}
+
+const TOOLS: [(&str, Tool); 4] = [
+    (
+        "WAYLAND_DISPLAY",
+        Tool {
+            copy: &["wl-copy"],
+            paste: &["wl-paste", "--no-newline"],
+        },
+    ),
+    (
+        "DISPLAY",
+        Tool {
+            copy: &["xclip", "-selection", "clipboard"],
+            paste: &["xclip", "-selection", "clipboard", "-o"],
+        },
+    ),
+    (
+        "DISPLAY",
+        Tool {
+            copy: &["xsel", "--clipboard", "--input"],
+            paste: &["xsel", "--clipboard", "--output"],
+        },
+    ),
+    (
+        "",
+        Tool {
+            copy: &["pbcopy"],
+            paste: &["pbpaste"],
+        },
+    ),
+];
+
+#[derive(Default)]
+pub struct Clipboard {
+    // the text last copied in the editor, used when the provider can't be read from or failed
+    register: String,
+    provider: Option<Box<dyn Provider>>,
+}
+
+impl Clipboard {
+    pub fn detect() -> Self {
+        // picks a local clipboard tool when one is installed for the current session,
+        // and the terminal clipboard for remote sessions or when no tool is found
+        let is_remote = env::var_os("SSH_CONNECTION").is_some() || env::var_os("SSH_TTY").is_some();
+        let tool = TOOLS.into_iter().find(|(variable, tool)| {
+            !is_remote
+                && (variable.is_empty() || env::var_os(variable).is_some())
+                && is_installed(tool.copy[0])
+                && is_installed(tool.paste[0])
+        });
+        let provider: Box<dyn Provider> = match tool {
+            Some((_, tool)) => Box::new(tool),
+            None => Box::new(Osc52),
+        };
+        Clipboard {
+            register: String::new(),
+            provider: Some(provider),
+        }
+    }
+
+    pub fn copy(&mut self, text: String) -> io::Result<()> {
+        let result = match &self.provider {
+            Some(provider) => provider.copy(&text),
+            None => Ok(()),
+        };
+        self.register = text;
+        result
+    }
+
+    pub fn paste(&self) -> String {
+        match &self.provider {
+            Some(provider) => match provider.paste() {
+                Ok(Some(text)) => text,
+                _ => self.register.clone(),
+            },
+            None => self.register.clone(),
+        }
+    }
+}
+
+fn is_installed(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|directory| Path::new(&directory).join(program).is_file())
+    })
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clipboard_falls_back_to_register() {
+        assert_eq!(base64(b"ab\nc"), "YWIKYw==");
+        assert_eq!(base64("日本".as_bytes()), "5pel5pys");
+
+        let mut clipboard = Clipboard {
+            register: String::new(),
+            provider: Some(Box::new(Osc52)),
+        };
+        clipboard.register = "text".to_string();
+        assert_eq!(clipboard.paste(), "text");
+    }
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::path::Path;
+
+use crate::input::parse_position;
+use crate::options::SETTINGS;
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Command {
+    // save, to another file when a path is given
+    Write(Option<String>),
+    // close the buffer, `q!` discards unsaved changes
+    Quit { force: bool },
+    Edit(String),
+    Goto((usize, usize)),
+    Set(String),
+}
+
+// names completed at the start of the command line, `w`, `q` and `e` are short for the first three
+const COMMANDS: &[&str] = &["write", "quit", "edit", "goto", "set"];
+
+pub fn parse(text: &str) -> Result<Command, String> {
+    let text = text.trim();
+    let (name, argument) = text.split_once(' ').unwrap_or((text, ""));
+    let argument = argument.trim();
+    match name {
+        "w" | "write" if argument.is_empty() => Ok(Command::Write(None)),
+        "w" | "write" => Ok(Command::Write(Some(argument.to_string()))),
+        "q" | "quit" => Ok(Command::Quit { force: false }),
+        "q!" | "quit!" => Ok(Command::Quit { force: true }),
+        "e" | "edit" if argument.is_empty() => Err("edit needs a file name".to_string()),
+        "e" | "edit" => Ok(Command::Edit(argument.to_string())),
+        "goto" => parse_position(argument)
+            .map(Command::Goto)
+            .ok_or("goto needs a line or line:col".to_string()),
+        "set" if argument.is_empty() => Err("set needs a setting".to_string()),
+        "set" => Ok(Command::Set(argument.to_string())),
+        "" => Err("no command".to_string()),
+        _ => Err(format!("unknown command {}", name)),
+    }
+}
+
+pub fn complete(text: &str) -> Vec<String> {
+    // the command lines `text` can be completed to, completing its last word
+    let Some((name, argument)) = text.split_once(' ') else {
+        return COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(text))
+            .map(|command| format!("{} ", command))
+            .collect();
+    };
+    let words = match name {
+        "w" | "write" | "e" | "edit" => complete_path(argument),
+        "set" => SETTINGS
+            .iter()
+            .filter(|setting| setting.starts_with(argument))
+            .map(|setting| setting.to_string())
+            .collect(),
+        _ => vec![],
+    };
+    words
+        .into_iter()
+        .map(|word| format!("{} {}", name, word))
+        .collect()
+}
+
+fn complete_path(prefix: &str) -> Vec<String> {
+    // files in the directory of `prefix` whose name starts like it, directories end with `/`
+    let (directory, name) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+        None => ("", prefix),
+    };
+    let Ok(entries) = fs::read_dir(if directory.is_empty() { "." } else { directory }) else {
+        return vec![];
+    };
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            // hidden files only when asked for
+            if !file_name.starts_with(name)
+                || (file_name.starts_with('.') && !name.starts_with('.'))
+            {
+                return None;
+            }
+            let path = format!("{}{}", directory, file_name);
+            Some(if Path::new(&path).is_dir() {
+                path + "/"
+            } else {
+                path
+            })
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[derive(Default)]
+pub struct CommandLine {
+    // the command being typed, with the commands run before it and the completions of Tab
+    pub text: String,
+    history: Vec<String>,
+    // position in `history` while going through it with Up and Down, `history.len()` is the new command
+    history_index: usize,
+    completions: Vec<String>,
+    completion_index: usize,
+}
+
+impl CommandLine {
+    pub fn start(&mut self) {
+        self.text = String::new();
+        self.history_index = self.history.len();
+        self.completions = vec![];
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.completions = vec![];
+    }
+
+    pub fn pop(&mut self) {
+        self.text.pop();
+        self.completions = vec![];
+    }
+
+    pub fn complete(&mut self) {
+        // the first Tab completes the last word, the following ones go through the other completions
+        if self.completions.is_empty() {
+            self.completions = complete(&self.text);
+            self.completion_index = 0;
+        } else {
+            self.completion_index = (self.completion_index + 1) % self.completions.len();
+        }
+        if let Some(completion) = self.completions.get(self.completion_index) {
+            self.text = completion.clone();
+        }
+        // a single completion is final, the next Tab completes the word after it
+        if self.completions.len() == 1 {
+            self.completions = vec![];
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if self.history_index > 0 {
+            self.history_index -= 1;
+            self.text = self.history[self.history_index].clone();
+            self.completions = vec![];
+        }
+    }
+
+    pub fn next(&mut self) {
+        if self.history_index < self.history.len() {
+            self.history_index += 1;
+            self.text = self
+                .history
+                .get(self.history_index)
+                .cloned()
+                .unwrap_or_default();
+            self.completions = vec![];
+        }
+    }
+
+    pub fn take(&mut self) -> String {
+        // the typed command, remembered unless it repeats the last one
+        let text = std::mem::take(&mut self.text);
+        if !text.trim().is_empty() && self.history.last() != Some(&text) {
+            self.history.push(text.clone());
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse("w"), Ok(Command::Write(None)));
+        assert_eq!(
+            parse(" w notes.txt "),
+            Ok(Command::Write(Some("notes.txt".to_string())))
+        );
+        assert_eq!(parse("q!"), Ok(Command::Quit { force: true }));
+        assert_eq!(parse("goto 120"), Ok(Command::Goto((119, 0))));
+        assert_eq!(
+            parse("set tabwidth=4"),
+            Ok(Command::Set("tabwidth=4".to_string()))
+        );
+        assert!(parse("e").is_err());
+        assert!(parse("goto x").is_err());
+        assert!(parse("nope").is_err());
+    }
+
+    #[test]
+    fn command_line_completes_and_remembers_commands() {
+        let mut command_line = CommandLine::default();
+        command_line.start();
+        command_line.push_str("s");
+        command_line.complete();
+        assert_eq!(command_line.text, "set ");
+        command_line.push_str("wr");
+        command_line.complete();
+        assert_eq!(command_line.text, "set wrap");
+        command_line.complete();
+        assert_eq!(command_line.text, "set wrap=char");
+        assert_eq!(command_line.take(), "set wrap=char");
+
+        command_line.start();
+        command_line.push_str("e src/ma");
+        command_line.complete();
+        assert_eq!(command_line.text, "e src/main.rs");
+        command_line.take();
+
+        command_line.start();
+        command_line.previous();
+        assert_eq!(command_line.text, "e src/main.rs");
+        command_line.previous();
+        assert_eq!(command_line.text, "set wrap=char");
+        command_line.next();
+        command_line.next();
+        assert_eq!(command_line.text, "");
+    }
+}
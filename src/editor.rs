@@ -1,12 +1,19 @@
-use std::io::{stdin, stdout, Error, Stdout, Write};
+use std::io::{self, stdin, stdout, Error, Read, Stdout, Write};
+use std::path::Path;
+
 use termion::color;
 
-use termion::cursor::DetectCursorPos;
 use termion::event::{Event, Key};
-use termion::input::TermRead;
+use termion::input::{EventsAndRaw, TermReadEventsAndRaw};
 use termion::raw::{IntoRawMode, RawTerminal};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::buffer::Buffer;
+use crate::clipboard::Clipboard;
+use crate::command::{self, Command, CommandLine};
+use crate::input::{parse_modified, parse_position, paste_text, Modified, PASTE_END, PASTE_START};
+use crate::options::Options;
+use crate::replace::{self, Replace};
 
 #[derive(Default)]
 enum EditorState {
@@ -14,6 +21,21 @@ enum EditorState {
     Init,
     Buffer,
     TakingFileInput,
+    // asking whether to create the missing directory of the file being saved
+    CreatingDirectory,
+    // asking whether to save the unsaved changes of the buffer being closed
+    ConfirmingClose,
+    // incremental search, the cursor follows the matches while typing the query
+    Searching,
+    // typing the regular expression and then the replacement of a find-and-replace
+    TakingPattern,
+    TakingReplacement,
+    // asking what to do with each match of a find-and-replace
+    ConfirmingReplace,
+    // typing a `line` or `line:col` to go to
+    GoingToLine,
+    // typing a command like `w`, `goto 120` or `set wrap` on the bottom row
+    TakingCommand,
 }
 
 #[derive(Default)]
@@ -24,15 +46,64 @@ pub struct Editor {
     exit: bool,
     mode: EditorState,
     error_message: String,
+    // closing every buffer to exit the editor
+    quitting: bool,
+    // exit when the last buffer is closed instead of going back to the init screen
+    wait: bool,
+    // a file was closed without saving it, or with changes made after the last save,
+    // the caller treats the edit as cancelled
+    is_cancelled: bool,
+    options: Options,
+    query: String,
+    // cursor position to go back to when the search is cancelled
+    search_origin: (usize, usize),
+    replacement: String,
+    replace: Option<Replace>,
+    command_line: CommandLine,
+    clipboard: Clipboard,
+    // kept between reads so bytes read ahead by termion aren't lost
+    events: Option<EventsAndRaw<Box<dyn Read>>>,
 }
 
 impl Editor {
-    pub fn run(&mut self) {
+    pub fn new(options: Options) -> Self {
+        Editor {
+            options,
+            clipboard: Clipboard::detect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn wait(&mut self) {
+        // used as $EDITOR, the program that started the editor goes on once the files are closed
+        self.wait = true;
+    }
+
+    pub fn open(&mut self, filename: &str, position: Option<(usize, usize)>) -> io::Result<()> {
+        // opens a file given on the command line, the first file opened is shown
+        let mut buffer = Buffer::new(filename, self.options.clone())?;
+        if let Some(position) = position {
+            buffer.goto(position);
+        }
+        self.buffers.push(buffer);
+        self.mode = EditorState::Buffer;
+        Ok(())
+    }
+
+    pub fn open_scratch(&mut self, text: &str) {
+        self.buffers
+            .push(Buffer::scratch(text, self.options.clone()));
+        self.mode = EditorState::Buffer;
+    }
+
+    pub fn run(&mut self) -> bool {
+        // returns false when a file was closed without saving it
         let mut stdout = stdout().into_raw_mode().unwrap();
 
+        // bracketed paste makes the terminal wrap pasted text in `PASTE_START` and `PASTE_END`
         write!(
             stdout,
-            "{}{}",
+            "\x1b[?2004h{}{}",
             termion::clear::All,
             termion::cursor::Goto(1, 1)
         )
@@ -42,23 +113,54 @@ impl Editor {
             self.render(&mut stdout);
 
             if self.exit {
-                print!("{}{}", termion::clear::All, termion::cursor::Goto(1, 1));
+                print!(
+                    "\x1b[?2004l{}{}",
+                    termion::clear::All,
+                    termion::cursor::Goto(1, 1)
+                );
                 break;
             }
             self.process_input_event();
         }
+        !self.is_cancelled
     }
 
-    fn read_next_event(&self) -> Result<Event, Error> {
+    fn read_next_event(&mut self) -> Result<(Event, Vec<u8>), Error> {
+        let events = self.events.get_or_insert_with(|| {
+            // keys come from the terminal even when stdin was used to pipe in text
+            let input: Box<dyn Read> = match termion::get_tty() {
+                Ok(tty) if !termion::is_tty(&stdin()) => Box::new(tty),
+                _ => Box::new(stdin()),
+            };
+            input.events_and_raw()
+        });
         loop {
-            if let Some(event) = stdin().events().next() {
+            if let Some(event) = events.next() {
                 return event;
             }
         }
     }
 
+    fn read_paste(&mut self) -> String {
+        // collects the raw bytes of a bracketed paste up to its end sequence
+        let mut bytes = Vec::new();
+        loop {
+            match self.read_next_event() {
+                Ok((_, raw)) if raw == PASTE_END => break,
+                Ok((_, raw)) => bytes.extend(raw),
+                Err(_) => {}
+            }
+        }
+        paste_text(&bytes)
+    }
+
     fn process_input_event(&mut self) {
-        let event = self.read_next_event().unwrap();
+        let (event, raw) = self.read_next_event().unwrap();
+        if raw == PASTE_START {
+            let text = self.read_paste();
+            self.paste(&text);
+            return;
+        }
 
         match self.mode {
             EditorState::Init => match event {
@@ -70,18 +172,27 @@ impl Editor {
                 _ => {}
             },
             EditorState::Buffer => {
+                self.error_message = String::new();
                 let buffer = &mut self.buffers[self.buffer_index];
+                if buffer.is_readonly() && is_edit(&event) {
+                    self.error_message = "read-only".to_string();
+                    return;
+                }
 
                 match event {
                     Event::Key(Key::Esc) => {
                         print!("{}", termion::clear::All);
-                        self.drop_buffer();
+                        self.close_buffer();
+                    }
+                    Event::Key(Key::Ctrl('q')) => {
+                        self.quitting = true;
+                        self.close_buffer();
                     }
                     Event::Key(Key::Char(char)) => {
                         buffer.write(char);
                     }
                     Event::Key(Key::Ctrl('s')) => {
-                        buffer.save().unwrap();
+                        self.save_buffer();
                     }
                     Event::Key(Key::Ctrl('n')) => self.mode = EditorState::TakingFileInput,
                     Event::Key(Key::Ctrl('r')) => {
@@ -90,9 +201,40 @@ impl Editor {
                     Event::Key(Key::Ctrl('w')) => {
                         self.buffers[self.buffer_index].toggle_wrapping();
                     }
+                    Event::Key(Key::Ctrl('l')) => buffer.cycle_line_numbers(),
                     Event::Key(Key::Backspace) => {
                         buffer.delete();
                     }
+                    Event::Key(Key::Delete) => buffer.delete_forward(),
+                    // terminals send Ctrl + Backspace as Ctrl + H or Alt + Backspace
+                    Event::Key(Key::Ctrl('h')) | Event::Key(Key::Alt('\x7f')) => {
+                        buffer.delete_word_backward()
+                    }
+                    Event::Key(Key::Ctrl('z')) => buffer.undo(),
+                    Event::Key(Key::Ctrl('y')) => buffer.redo(),
+                    Event::Key(Key::Ctrl('e')) => buffer.toggle_line_endings(),
+                    Event::Key(Key::Ctrl('f')) => {
+                        self.search_origin = buffer.position();
+                        self.query = String::new();
+                        self.mode = EditorState::Searching;
+                    }
+                    Event::Key(Key::Ctrl('g')) => {
+                        self.query = String::new();
+                        self.mode = EditorState::GoingToLine;
+                    }
+                    Event::Key(Key::Ctrl('p')) => {
+                        self.command_line.start();
+                        self.mode = EditorState::TakingCommand;
+                    }
+                    Event::Key(Key::Home) => buffer.home(),
+                    Event::Key(Key::End) => buffer.end(),
+                    Event::Key(Key::PageUp) => buffer.page_up(),
+                    Event::Key(Key::PageDown) => buffer.page_down(),
+                    Event::Key(Key::Ctrl('t')) => {
+                        self.query = String::new();
+                        self.replacement = String::new();
+                        self.mode = EditorState::TakingPattern;
+                    }
                     Event::Key(Key::Up) => {
                         buffer.up();
                     }
@@ -103,6 +245,29 @@ impl Editor {
                     Event::Key(Key::Right) => {
                         buffer.right();
                     }
+                    Event::Key(Key::Ctrl('c')) => self.copy_selection(false),
+                    Event::Key(Key::Ctrl('x')) => self.copy_selection(true),
+                    Event::Key(Key::Ctrl('v')) => buffer.paste(&self.clipboard.paste()),
+                    Event::Unsupported(bytes) => match parse_modified(&bytes) {
+                        Some(Modified::Shift(Key::Up)) => buffer.select(Buffer::up),
+                        Some(Modified::Shift(Key::Down)) => buffer.select(Buffer::down),
+                        Some(Modified::Shift(Key::Left)) => buffer.select(Buffer::left),
+                        Some(Modified::Shift(Key::Right)) => buffer.select(Buffer::right),
+                        Some(Modified::Ctrl(Key::Left)) => buffer.word_left(),
+                        Some(Modified::Ctrl(Key::Right)) => buffer.word_right(),
+                        Some(Modified::CtrlShift(Key::Left)) => buffer.select(Buffer::word_left),
+                        Some(Modified::CtrlShift(Key::Right)) => buffer.select(Buffer::word_right),
+                        Some(Modified::Ctrl(Key::Delete)) => buffer.delete_word_forward(),
+                        Some(Modified::Shift(Key::Home)) => buffer.select(Buffer::home),
+                        Some(Modified::Shift(Key::End)) => buffer.select(Buffer::end),
+                        Some(Modified::Shift(Key::PageUp)) => buffer.select(Buffer::page_up),
+                        Some(Modified::Shift(Key::PageDown)) => buffer.select(Buffer::page_down),
+                        Some(Modified::Ctrl(Key::Home)) => buffer.file_start(),
+                        Some(Modified::Ctrl(Key::End)) => buffer.file_end(),
+                        Some(Modified::CtrlShift(Key::Home)) => buffer.select(Buffer::file_start),
+                        Some(Modified::CtrlShift(Key::End)) => buffer.select(Buffer::file_end),
+                        _ => {}
+                    },
                     _ => {}
                 }
             }
@@ -126,6 +291,152 @@ impl Editor {
                 }
                 _ => {}
             },
+            EditorState::CreatingDirectory => match event {
+                Event::Key(Key::Char('y')) => {
+                    self.mode = EditorState::Buffer;
+                    let buffer = &self.buffers[self.buffer_index];
+                    match buffer.create_directory() {
+                        Ok(()) => self.save_buffer(),
+                        Err(err) => {
+                            self.error_message = format!("could not create directory: {}", err)
+                        }
+                    }
+                }
+                Event::Key(Key::Char('n')) | Event::Key(Key::Esc) => {
+                    self.mode = EditorState::Buffer;
+                    self.error_message = "file not saved".to_string();
+                }
+                _ => {}
+            },
+            EditorState::ConfirmingClose => match event {
+                Event::Key(Key::Char('s')) => {
+                    self.mode = EditorState::Buffer;
+                    self.save_buffer();
+                    if self.buffers[self.buffer_index].is_dirty() {
+                        self.quitting = false;
+                    } else {
+                        self.close_buffer();
+                    }
+                }
+                Event::Key(Key::Char('d')) => {
+                    self.mode = EditorState::Buffer;
+                    self.drop_buffer();
+                }
+                Event::Key(Key::Char('c')) | Event::Key(Key::Esc) => {
+                    self.mode = EditorState::Buffer;
+                    self.quitting = false;
+                }
+                _ => {}
+            },
+            EditorState::Searching => {
+                let buffer = &mut self.buffers[self.buffer_index];
+                let (row, col) = buffer.position();
+                match event {
+                    Event::Key(Key::Esc) => {
+                        buffer.goto(self.search_origin);
+                        self.stop_search();
+                    }
+                    Event::Key(Key::Char('\n')) => self.stop_search(),
+                    Event::Key(Key::Char(char)) => {
+                        self.query.push(char);
+                        self.search(self.search_origin, true);
+                    }
+                    Event::Key(Key::Backspace) => {
+                        self.query.pop();
+                        if self.query.is_empty() {
+                            buffer.goto(self.search_origin);
+                        }
+                        self.search(self.search_origin, true);
+                    }
+                    Event::Key(Key::Down) | Event::Key(Key::Ctrl('n')) => {
+                        self.search((row, col + 1), true)
+                    }
+                    Event::Key(Key::Up) | Event::Key(Key::Ctrl('p')) => {
+                        self.search((row, col), false)
+                    }
+                    _ => {}
+                }
+            }
+            EditorState::TakingPattern => match event {
+                Event::Key(Key::Esc) => self.mode = EditorState::Buffer,
+                Event::Key(Key::Char('\n')) => match replace::compile(&self.query) {
+                    Ok(_) => {
+                        self.error_message = String::new();
+                        self.mode = EditorState::TakingReplacement;
+                    }
+                    Err(_) => self.error_message = "invalid regex".to_string(),
+                },
+                Event::Key(Key::Char(char)) => self.query.push(char),
+                Event::Key(Key::Backspace) => {
+                    self.query.pop();
+                }
+                _ => {}
+            },
+            EditorState::TakingReplacement => match event {
+                Event::Key(Key::Esc) => self.mode = EditorState::Buffer,
+                Event::Key(Key::Char('\n')) => self.start_replace(),
+                Event::Key(Key::Char(char)) => self.replacement.push(char),
+                Event::Key(Key::Backspace) => {
+                    self.replacement.pop();
+                }
+                _ => {}
+            },
+            EditorState::ConfirmingReplace => {
+                let buffer = &mut self.buffers[self.buffer_index];
+                let Some(replace) = self.replace.as_mut() else {
+                    self.mode = EditorState::Buffer;
+                    return;
+                };
+                let is_done = match event {
+                    Event::Key(Key::Char('y')) => {
+                        buffer.replace_current(replace);
+                        !buffer.next_replace(replace)
+                    }
+                    Event::Key(Key::Char('n')) => {
+                        buffer.skip_current(replace);
+                        !buffer.next_replace(replace)
+                    }
+                    Event::Key(Key::Char('a')) => {
+                        buffer.replace_all(replace);
+                        true
+                    }
+                    Event::Key(Key::Char('q')) | Event::Key(Key::Esc) => true,
+                    _ => false,
+                };
+                if is_done {
+                    self.finish_replace();
+                }
+            }
+            EditorState::GoingToLine => match event {
+                Event::Key(Key::Esc) => self.mode = EditorState::Buffer,
+                Event::Key(Key::Char('\n')) => match parse_position(&self.query) {
+                    Some(position) => {
+                        self.error_message = String::new();
+                        self.buffers[self.buffer_index].goto(position);
+                        self.mode = EditorState::Buffer;
+                    }
+                    None => self.error_message = "invalid line".to_string(),
+                },
+                Event::Key(Key::Char(char)) => self.query.push(char),
+                Event::Key(Key::Backspace) => {
+                    self.query.pop();
+                }
+                _ => {}
+            },
+            EditorState::TakingCommand => match event {
+                Event::Key(Key::Esc) => self.mode = EditorState::Buffer,
+                Event::Key(Key::Char('\n')) => {
+                    self.mode = EditorState::Buffer;
+                    let text = self.command_line.take();
+                    self.run_command(&text);
+                }
+                Event::Key(Key::Char('\t')) => self.command_line.complete(),
+                Event::Key(Key::Char(char)) => self.command_line.push_str(&char.to_string()),
+                Event::Key(Key::Backspace) => self.command_line.pop(),
+                Event::Key(Key::Up) => self.command_line.previous(),
+                Event::Key(Key::Down) => self.command_line.next(),
+                _ => {}
+            },
         }
     }
 
@@ -136,46 +447,156 @@ impl Editor {
                 self.render_init_screen();
             }
             EditorState::Buffer => {
-                self.render_buffer(stdout);
+                self.render_buffer();
             }
             EditorState::TakingFileInput => {
                 self.render_file_input();
             }
+            EditorState::CreatingDirectory => {
+                self.render_buffer();
+                let buffer = &self.buffers[self.buffer_index];
+                let directory = buffer.missing_directory().unwrap_or(Path::new(""));
+                self.render_prompt(&format!(
+                    "directory {} does not exist, create it? (y/n)",
+                    directory.display()
+                ));
+            }
+            EditorState::ConfirmingClose => {
+                self.render_buffer();
+                let buffer = &self.buffers[self.buffer_index];
+                self.render_prompt(&format!(
+                    "{} has unsaved changes: (s)ave, (d)iscard or (c)ancel",
+                    buffer.filename()
+                ));
+            }
+            EditorState::Searching => {
+                self.render_buffer();
+                let status = if self.error_message.is_empty() {
+                    "Up/Down: previous/next, Esc: cancel"
+                } else {
+                    "not found"
+                };
+                self.render_prompt(&format!("search ({}): {}", status, self.query));
+            }
+            EditorState::TakingPattern => {
+                self.render_buffer();
+                let status = if self.error_message.is_empty() {
+                    "regex"
+                } else {
+                    "invalid regex"
+                };
+                self.render_prompt(&format!("replace ({}): {}", status, self.query));
+            }
+            EditorState::TakingReplacement => {
+                self.render_buffer();
+                self.render_prompt(&format!(
+                    "replace /{}/ with ($1 for groups): {}",
+                    self.query, self.replacement
+                ));
+            }
+            EditorState::ConfirmingReplace => {
+                self.render_buffer();
+                self.render_prompt("replace this match? (y)es, (n)o, (a)ll or (q)uit");
+            }
+            EditorState::GoingToLine => {
+                self.render_buffer();
+                let status = if self.error_message.is_empty() {
+                    "line or line:col"
+                } else {
+                    "invalid line"
+                };
+                self.render_prompt(&format!("go to ({}): {}", status, self.query));
+            }
+            EditorState::TakingCommand => {
+                self.render_buffer();
+                self.render_prompt(&format!(":{}", self.command_line.text));
+            }
         }
 
         stdout.flush().unwrap();
     }
 
-    fn render_buffer(&mut self, stdout: &mut RawTerminal<Stdout>) {
+    fn render_buffer(&mut self) {
         let buffer = &mut self.buffers[self.buffer_index];
 
-        let (row, col) = stdout.cursor_pos().unwrap();
-
         print!("{}{}", termion::clear::All, termion::cursor::Goto(1, 1));
         buffer.render();
         let (y, x) = termion::terminal_size().unwrap();
 
-        let row_col_string = &*format!("{}:{}", buffer.buffer_row(), buffer.buffer_col(),);
+        // byte column, followed by the display column when they differ, 1-based like go-to-line
+        let (byte_col, display_col) = buffer.columns();
+        let col = if byte_col == display_col {
+            (byte_col + 1).to_string()
+        } else {
+            format!("{}-{}", byte_col + 1, display_col + 1)
+        };
+        let row_col_string = &*format!(
+            "{}{}  {}:{}",
+            buffer
+                .file_type()
+                .map_or(String::new(), |file_type| format!("{}  ", file_type)),
+            buffer.line_ending_label(),
+            buffer.buffer_row() + 1,
+            col,
+        );
 
-        let empty_white_space =
-            " ".repeat(y as usize - row_col_string.len() - buffer.filename().len());
+        let title = if !self.error_message.is_empty() {
+            self.error_message.clone()
+        } else {
+            let mut title = if buffer.is_scratch() {
+                "[scratch]".to_string()
+            } else {
+                buffer.filename().to_string()
+            };
+            if buffer.is_readonly() {
+                title.push_str(" [read-only]");
+            }
+            if buffer.is_dirty() {
+                title.push_str(" [+]");
+            }
+            if buffer.is_new() {
+                title.push_str(" [new]");
+            }
+            title
+        };
+
+        // on a narrow terminal the title gives way first, then the start of the (ASCII) status
+        let width = y as usize;
+        let status = &row_col_string[row_col_string.len().saturating_sub(width)..];
+        let title = truncate(&title, width - status.len());
+        let empty_white_space = " ".repeat(width - status.len() - title.width());
 
         print!("{}{}", color::Bg(color::White), color::Fg(color::Black));
         print!(
-            "{}{}{}",
+            "{}{}{}{}",
             termion::cursor::Goto(1, x),
-            buffer.filename(),
-            empty_white_space
+            title,
+            empty_white_space,
+            status
         );
 
+        print!("{}{}", color::Bg(color::Reset), color::Fg(color::Reset));
+        buffer.cursor().render();
+    }
+
+    fn render_prompt(&self, prompt: &str) {
+        // draws `prompt` over the status bar and leaves the cursor after it
+        let (y, x) = termion::terminal_size().unwrap();
+        let prompt_width = prompt.width();
+        let empty_white_space = " ".repeat((y as usize).saturating_sub(prompt_width));
+
+        print!("{}{}", color::Bg(color::White), color::Fg(color::Black));
         print!(
-            "{}{}",
-            termion::cursor::Goto(y + 1 - row_col_string.len() as u16, x),
-            row_col_string
+            "{}{}{}",
+            termion::cursor::Goto(1, x),
+            prompt,
+            empty_white_space
         );
-
         print!("{}{}", color::Bg(color::Reset), color::Fg(color::Reset));
-        print!("{}", termion::cursor::Goto(row, col));
+        print!(
+            "{}",
+            termion::cursor::Goto((prompt_width as u16 + 1).min(y), x)
+        );
     }
 
     fn render_file_input(&mut self) {
@@ -210,10 +631,20 @@ impl Editor {
         print!("Ctrl + R : to rotate between open files\n\r");
         print!("Ctrl + S : Save current file\n\r");
         print!("~\n\r");
-        print!("Ctrl + W : Toggle text wrapping\n\r");
-        print!("Arrows   : cursor movement\n\r");
-        print!("Backspace: erase character\n\r");
+        print!("Ctrl + W : Cycle text wrapping (off/character/word)\n\r");
+        print!("Ctrl + L : Cycle line numbers (off/absolute/relative/hybrid)\n\r");
+        print!("Ctrl + E : Convert line endings (LF/CRLF)\n\r");
+        print!("Ctrl + F : Search in current file\n\r");
+        print!("Ctrl + T : Find and replace with a regex\n\r");
+        print!("Ctrl + G : Go to line\n\r");
+        print!("Ctrl + P : Command line (w, q, e, goto, set), Tab completes\n\r");
+        print!("Arrows, Home/End, PageUp/PageDown: cursor movement, Shift to select\n\r");
+        print!("Ctrl + Left/Right, Ctrl + Home/End: move by word, to file start/end\n\r");
+        print!("Backspace/Delete, Ctrl + Backspace/Delete: erase character, word\n\r");
+        print!("Ctrl + C / X / V: Copy / Cut / Paste\n\r");
+        print!("Ctrl + Z / Y: Undo / Redo\n\r");
         print!("~\n\r");
+        print!("Ctrl + Q : Close all files and exit\n\r");
         print!("Esc      : Exit\n\r");
 
         let (y, x) = termion::terminal_size().unwrap();
@@ -234,23 +665,187 @@ impl Editor {
 
     fn open_buffer(&mut self) {
         print!("{}{}", termion::clear::All, termion::cursor::Goto(1, 1));
-        if let Ok(buffer) = Buffer::new(self.filename.as_str()) {
-            self.buffers.push(buffer);
-            self.buffer_index = self.buffers.len() - 1;
+        if self.filename.is_empty() {
+            self.error_message = "enter a filename to open".to_string();
+            return;
+        }
+        match Buffer::new(self.filename.as_str(), self.options.clone()) {
+            Ok(buffer) => {
+                self.buffers.push(buffer);
+                self.buffer_index = self.buffers.len() - 1;
+                self.mode = EditorState::Buffer;
+                self.filename = String::new();
+                self.error_message = String::new();
+            }
+            Err(err) => {
+                self.error_message = format!("could not open {}: {}", self.filename, err);
+                self.filename = String::new();
+            }
+        }
+    }
+
+    fn save_buffer(&mut self) {
+        let buffer = &mut self.buffers[self.buffer_index];
+        if buffer.is_scratch() {
+            self.error_message = "the scratch buffer has no file to save to".to_string();
+            return;
+        }
+        if buffer.missing_directory().is_some() {
+            self.mode = EditorState::CreatingDirectory;
+            return;
+        }
+        if let Err(err) = buffer.save() {
+            self.error_message = format!("could not save {}: {}", buffer.filename(), err);
+        }
+    }
+
+    fn paste(&mut self, text: &str) {
+        // a pasted block is inserted as a single edit, prompts take its first line
+        let line = text.lines().next().unwrap_or_default();
+        match self.mode {
+            EditorState::Buffer if self.buffers[self.buffer_index].is_readonly() => {
+                self.error_message = "read-only".to_string();
+            }
+            EditorState::Buffer => {
+                self.error_message = String::new();
+                self.buffers[self.buffer_index].paste(text);
+            }
+            EditorState::TakingFileInput => self.filename.push_str(line),
+            EditorState::Searching => {
+                self.query.push_str(line);
+                self.search(self.search_origin, true);
+            }
+            EditorState::TakingPattern | EditorState::GoingToLine => self.query.push_str(line),
+            EditorState::TakingReplacement => self.replacement.push_str(line),
+            EditorState::TakingCommand => self.command_line.push_str(line),
+            _ => {}
+        }
+    }
+
+    fn run_command(&mut self, text: &str) {
+        let command = match command::parse(text) {
+            Ok(command) => command,
+            Err(err) => {
+                self.error_message = err;
+                return;
+            }
+        };
+        let buffer = &mut self.buffers[self.buffer_index];
+        match command {
+            Command::Write(_) if buffer.is_readonly() => {
+                self.error_message = "read-only".to_string();
+            }
+            Command::Write(filename) => {
+                if let Some(filename) = filename {
+                    buffer.set_filename(&filename);
+                }
+                self.save_buffer();
+            }
+            Command::Quit { force: false } if buffer.is_dirty() => {
+                self.error_message = "unsaved changes, save with w or discard with q!".to_string();
+            }
+            Command::Quit { .. } => {
+                print!("{}", termion::clear::All);
+                self.drop_buffer();
+            }
+            Command::Edit(filename) => {
+                self.filename = filename;
+                self.open_buffer();
+            }
+            Command::Goto(position) => buffer.goto(position),
+            Command::Set(setting) => {
+                // applies to the current buffer and the ones opened after it
+                if let Err(err) = buffer
+                    .set(&setting)
+                    .and_then(|()| self.options.set(&setting))
+                {
+                    self.error_message = err;
+                }
+            }
+        }
+    }
+
+    fn copy_selection(&mut self, is_cut: bool) {
+        let buffer = &mut self.buffers[self.buffer_index];
+        let Some(text) = buffer.selected_text() else {
+            self.error_message = "nothing selected".to_string();
+            return;
+        };
+        if is_cut {
+            buffer.delete_selection();
+        }
+        if let Err(err) = self.clipboard.copy(text) {
+            self.error_message = format!("copied inside the editor only: {}", err);
+        }
+    }
+
+    fn search(&mut self, from: (usize, usize), forward: bool) {
+        // moves the cursor to the closest match of the query and highlights every match
+        let buffer = &mut self.buffers[self.buffer_index];
+        buffer.set_search(&self.query);
+        self.error_message = String::new();
+        if self.query.is_empty() {
+            return;
+        }
+        match buffer.find(&self.query, from, forward) {
+            Some(position) => buffer.goto(position),
+            None => self.error_message = "not found".to_string(),
+        }
+    }
+
+    fn stop_search(&mut self) {
+        self.buffers[self.buffer_index].set_search("");
+        self.error_message = String::new();
+        self.mode = EditorState::Buffer;
+    }
+
+    fn start_replace(&mut self) {
+        let Ok(regex) = replace::compile(&self.query) else {
+            self.mode = EditorState::TakingPattern;
+            return;
+        };
+        let buffer = &mut self.buffers[self.buffer_index];
+        let mut replace = buffer.start_replace(regex, self.replacement.clone());
+        if buffer.next_replace(&mut replace) {
+            self.replace = Some(replace);
+            self.mode = EditorState::ConfirmingReplace;
+        } else {
+            buffer.finish_replace();
+            self.error_message = "no matches found".to_string();
             self.mode = EditorState::Buffer;
-            self.filename = String::new();
-            self.error_message = String::new();
+        }
+    }
+
+    fn finish_replace(&mut self) {
+        self.buffers[self.buffer_index].finish_replace();
+        if let Some(replace) = self.replace.take() {
+            self.error_message = format!("replaced {} occurrence(s)", replace.count);
+        }
+        self.mode = EditorState::Buffer;
+    }
+
+    fn close_buffer(&mut self) {
+        // drops the current buffer, asking first if it has unsaved changes
+        if self.buffers[self.buffer_index].is_dirty() {
+            self.mode = EditorState::ConfirmingClose;
         } else {
-            self.error_message = format!("file {} not found, enter correct path", self.filename);
-            self.filename = String::new();
+            self.drop_buffer();
         }
     }
 
     fn drop_buffer(&mut self) {
-        self.buffers.remove(self.buffer_index);
+        let buffer = self.buffers.remove(self.buffer_index);
+        if !buffer.is_scratch() && (buffer.is_dirty() || !buffer.is_saved()) {
+            self.is_cancelled = true;
+        }
         self.cycle_buffer();
         if self.buffers.is_empty() {
             self.mode = EditorState::Init;
+            if self.quitting || self.wait {
+                self.exit = true;
+            }
+        } else if self.quitting {
+            self.close_buffer();
         }
     }
 
@@ -261,3 +856,25 @@ impl Editor {
         }
     }
 }
+
+fn truncate(text: &str, width: usize) -> &str {
+    // the longest start of `text` that fits in `width` terminal columns
+    let mut x = 0;
+    for (i, char) in text.char_indices() {
+        x += char.width().unwrap_or(0);
+        if x > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+fn is_edit(event: &Event) -> bool {
+    // keys that change the text of a buffer, ignored in read-only buffers
+    match event {
+        Event::Key(Key::Char(_) | Key::Backspace | Key::Delete | Key::Alt('\x7f')) => true,
+        Event::Key(Key::Ctrl(key)) => "hxvzyets".contains(*key),
+        Event::Unsupported(bytes) => parse_modified(bytes) == Some(Modified::Ctrl(Key::Delete)),
+        _ => false,
+    }
+}
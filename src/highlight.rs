@@ -0,0 +1,611 @@
+use std::path::Path;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::style::{Span, Style};
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum State {
+    // what a line leaves open for the next one, highlighting a line only needs the state of the line above
+    #[default]
+    Normal,
+    // nesting depth of block comments
+    Comment(usize),
+    // index of the unclosed string in `Grammar::strings`
+    String(usize),
+    // inside a fenced code block of Markdown
+    Fenced,
+    // past the subject line of a commit message
+    Body,
+    // below the scissors line of a commit message, git ignores the rest of the file
+    Scissors,
+}
+
+pub trait Highlighter {
+    fn name(&self) -> &'static str;
+
+    // adds the styled parts of `line` to `spans` and returns the state at the end of the line
+    fn highlight(&self, line: &str, state: State, spans: &mut Vec<Span>) -> State;
+}
+
+pub fn detect(filename: &str, first_line: &str) -> Option<Box<dyn Highlighter>> {
+    // picks a highlighter by file extension or name, and by the shebang of scripts without an extension
+    let path = Path::new(filename);
+    let name = path.file_name()?.to_str()?;
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let grammar = match (extension, name) {
+        (Some("rs"), _) => RUST,
+        (Some("toml"), _) | (_, "Cargo.lock") => TOML,
+        (Some("json"), _) => JSON,
+        (Some("sh" | "bash" | "zsh"), _) | (_, ".bashrc" | ".profile" | ".zshrc") => SHELL,
+        (Some("py"), _) => PYTHON,
+        (Some("md" | "markdown"), _) => return Some(Box::new(Markdown)),
+        (_, "COMMIT_EDITMSG" | "MERGE_MSG" | "TAG_EDITMSG" | "SQUASH_MSG") => {
+            return Some(Box::new(GitCommit))
+        }
+        (_, "git-rebase-todo") => return Some(Box::new(GitRebase)),
+        _ => {
+            let interpreter = first_line.strip_prefix("#!")?.split_whitespace().last()?;
+            match interpreter.rsplit('/').next()? {
+                "sh" | "bash" | "zsh" | "dash" => SHELL,
+                name if name.starts_with("python") => PYTHON,
+                _ => return None,
+            }
+        }
+    };
+    Some(Box::new(grammar))
+}
+
+pub struct Grammar {
+    // describes the tokens of a language for the generic highlighter
+    name: &'static str,
+    line_comments: &'static [&'static str],
+    // opening and closing delimiters, block comments nest
+    block_comment: Option<(&'static str, &'static str)>,
+    // opening and closing delimiters, whether the string can span lines and whether `\` escapes
+    strings: &'static [(&'static str, &'static str, bool, bool)],
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    constants: &'static [&'static str],
+    // a name or string followed by this char is a key
+    key_separator: Option<char>,
+    // `'a'` is a char literal while `'a` is a lifetime
+    chars: bool,
+    // `$name` and `${name}` are variables
+    variables: bool,
+    // `[name]` lines are headings
+    tables: bool,
+    // names can contain `-`
+    dashes: bool,
+}
+
+const RUST: Grammar = Grammar {
+    name: "Rust",
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &[("\"", "\"", true, true)],
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "self", "static", "struct", "super", "trait", "type", "unsafe",
+        "use", "where", "while",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32",
+        "i64", "i128", "isize", "f32", "f64", "Self", "String", "Vec", "Option", "Result", "Box",
+    ],
+    constants: &["true", "false", "None", "Some", "Ok", "Err"],
+    key_separator: None,
+    chars: true,
+    variables: false,
+    tables: false,
+    dashes: false,
+};
+
+const TOML: Grammar = Grammar {
+    name: "TOML",
+    line_comments: &["#"],
+    block_comment: None,
+    strings: &[
+        ("\"\"\"", "\"\"\"", true, true),
+        ("'''", "'''", true, false),
+        ("\"", "\"", false, true),
+        ("'", "'", false, false),
+    ],
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false"],
+    key_separator: Some('='),
+    chars: false,
+    variables: false,
+    tables: true,
+    dashes: true,
+};
+
+const JSON: Grammar = Grammar {
+    name: "JSON",
+    line_comments: &[],
+    block_comment: None,
+    strings: &[("\"", "\"", false, true)],
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false", "null"],
+    key_separator: Some(':'),
+    chars: false,
+    variables: false,
+    tables: false,
+    dashes: false,
+};
+
+const SHELL: Grammar = Grammar {
+    name: "Shell",
+    line_comments: &["#"],
+    block_comment: None,
+    strings: &[("\"", "\"", true, true), ("'", "'", true, false)],
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+        "in", "function", "return", "local", "export", "readonly", "break", "continue", "exit",
+    ],
+    types: &[],
+    constants: &["true", "false"],
+    key_separator: None,
+    chars: false,
+    variables: true,
+    tables: false,
+    dashes: false,
+};
+
+const PYTHON: Grammar = Grammar {
+    name: "Python",
+    line_comments: &["#"],
+    block_comment: None,
+    strings: &[
+        ("\"\"\"", "\"\"\"", true, true),
+        ("'''", "'''", true, true),
+        ("\"", "\"", false, true),
+        ("'", "'", false, true),
+    ],
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+        "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is",
+        "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with",
+        "yield",
+    ],
+    types: &[
+        "bool", "bytes", "dict", "float", "int", "list", "object", "set", "str", "tuple",
+    ],
+    constants: &["True", "False", "None"],
+    key_separator: None,
+    chars: false,
+    variables: false,
+    tables: false,
+    dashes: false,
+};
+
+fn push(spans: &mut Vec<Span>, start: usize, end: usize, style: Style) {
+    if start < end {
+        spans.push(Span { start, end, style });
+    }
+}
+
+impl Grammar {
+    fn is_name_byte(&self, byte: u8) -> bool {
+        byte.is_ascii_alphanumeric() || byte == b'_' || (self.dashes && byte == b'-')
+    }
+
+    fn is_key(&self, rest: &str) -> bool {
+        // whether the text after a name or string makes it a key
+        self.key_separator
+            .is_some_and(|separator| rest.trim_start().starts_with(separator))
+    }
+}
+
+impl Highlighter for Grammar {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn highlight(&self, line: &str, mut state: State, spans: &mut Vec<Span>) -> State {
+        // all delimiters are ASCII, so token boundaries always fall on char boundaries
+        let bytes = line.as_bytes();
+        let len = bytes.len();
+        if self.tables && state == State::Normal && line.trim_start().starts_with('[') {
+            push(spans, 0, len, Style::Heading);
+            return State::Normal;
+        }
+
+        let mut i = 0;
+        let mut token_start = 0;
+        loop {
+            match state {
+                State::Comment(mut depth) => {
+                    let Some((open, close)) = self.block_comment else {
+                        return State::Normal;
+                    };
+                    while i < len && depth > 0 {
+                        if bytes[i..].starts_with(close.as_bytes()) {
+                            depth -= 1;
+                            i += close.len();
+                        } else if bytes[i..].starts_with(open.as_bytes()) {
+                            depth += 1;
+                            i += open.len();
+                        } else {
+                            i += 1;
+                        }
+                    }
+                    push(spans, token_start, i, Style::Comment);
+                    if depth > 0 {
+                        return State::Comment(depth);
+                    }
+                    state = State::Normal;
+                }
+                State::String(index) => {
+                    let (_, close, is_multiline, escapes) = self.strings[index];
+                    let mut is_closed = false;
+                    while i < len {
+                        if escapes && bytes[i] == b'\\' {
+                            i += 2;
+                        } else if bytes[i..].starts_with(close.as_bytes()) {
+                            i += close.len();
+                            is_closed = true;
+                            break;
+                        } else {
+                            i += 1;
+                        }
+                    }
+                    i = i.min(len);
+                    let style = if is_closed && self.is_key(&line[i..]) {
+                        Style::Key
+                    } else {
+                        Style::String
+                    };
+                    push(spans, token_start, i, style);
+                    if !is_closed {
+                        return if is_multiline {
+                            State::String(index)
+                        } else {
+                            State::Normal
+                        };
+                    }
+                    state = State::Normal;
+                }
+                _ => {
+                    if i >= len {
+                        return State::Normal;
+                    }
+                    token_start = i;
+                    let rest = &bytes[i..];
+                    if self.variables && rest[0] == b'$' && rest.len() > 1 {
+                        i += 1;
+                        if bytes[i] == b'{' {
+                            i = line[i..].find('}').map_or(len, |end| i + end + 1);
+                        } else if self.is_name_byte(bytes[i]) {
+                            while i < len && self.is_name_byte(bytes[i]) {
+                                i += 1;
+                            }
+                        } else if bytes[i].is_ascii_punctuation() {
+                            i += 1;
+                        }
+                        push(spans, token_start, i, Style::Type);
+                    } else if self
+                        .line_comments
+                        .iter()
+                        .any(|comment| rest.starts_with(comment.as_bytes()))
+                    {
+                        push(spans, i, len, Style::Comment);
+                        return State::Normal;
+                    } else if let Some((open, _)) = self
+                        .block_comment
+                        .filter(|(open, _)| rest.starts_with(open.as_bytes()))
+                    {
+                        i += open.len();
+                        state = State::Comment(1);
+                    } else if let Some(index) = self
+                        .strings
+                        .iter()
+                        .position(|(open, ..)| rest.starts_with(open.as_bytes()))
+                    {
+                        i += self.strings[index].0.len();
+                        state = State::String(index);
+                    } else if self.chars && rest[0] == b'\'' {
+                        // a lifetime is left unstyled
+                        let end = match line[i + 1..].chars().next() {
+                            Some('\\') => i + 3,
+                            Some(char) => i + 1 + char.len_utf8(),
+                            None => len,
+                        };
+                        i = if bytes.get(end) == Some(&b'\'') {
+                            push(spans, token_start, end + 1, Style::String);
+                            end + 1
+                        } else {
+                            i + 1
+                        };
+                    } else if rest[0].is_ascii_digit() {
+                        while i < len && (self.is_name_byte(bytes[i]) || bytes[i] == b'.') {
+                            i += 1;
+                        }
+                        push(spans, token_start, i, Style::Number);
+                    } else if self.is_name_byte(rest[0]) {
+                        while i < len && self.is_name_byte(bytes[i]) {
+                            i += 1;
+                        }
+                        let name = &line[token_start..i];
+                        let style = if self.keywords.contains(&name) {
+                            Some(Style::Keyword)
+                        } else if self.types.contains(&name) {
+                            Some(Style::Type)
+                        } else if self.constants.contains(&name) {
+                            Some(Style::Constant)
+                        } else if self.is_key(&line[i..]) {
+                            Some(Style::Key)
+                        } else {
+                            None
+                        };
+                        if let Some(style) = style {
+                            push(spans, token_start, i, style);
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct Markdown;
+
+impl Markdown {
+    fn list_marker(text: &str) -> usize {
+        // length of a `- `, `* `, `+ ` or `1. ` list marker at the start of `text`
+        let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+        let marker = if digits > 0 { &text[digits..] } else { text };
+        let is_marker = if digits > 0 {
+            marker.starts_with(". ")
+        } else {
+            ["- ", "* ", "+ "]
+                .iter()
+                .any(|bullet| marker.starts_with(bullet))
+        };
+        if is_marker {
+            digits + 2
+        } else {
+            0
+        }
+    }
+
+    fn inline(line: &str, mut i: usize, spans: &mut Vec<Span>) {
+        // inline code and emphasis, `_` only counts at the start of a word so snake_case is left alone
+        let bytes = line.as_bytes();
+        while i < bytes.len() {
+            let delimiter = bytes[i];
+            let is_word_start = i == 0 || !bytes[i - 1].is_ascii_alphanumeric();
+            let run = match delimiter {
+                b'`' => "`",
+                b'*' if bytes[i..].starts_with(b"**") => "**",
+                b'*' => "*",
+                b'_' if is_word_start && bytes[i..].starts_with(b"__") => "__",
+                b'_' if is_word_start => "_",
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let content = i + run.len();
+            match line[content..].find(run).filter(|&len| len > 0) {
+                Some(len) => {
+                    let end = content + len + run.len();
+                    let style = if delimiter == b'`' {
+                        Style::Code
+                    } else {
+                        Style::Emphasis
+                    };
+                    push(spans, i, end, style);
+                    i = end;
+                }
+                None => i = content,
+            }
+        }
+    }
+}
+
+impl Highlighter for Markdown {
+    fn name(&self) -> &'static str {
+        "Markdown"
+    }
+
+    fn highlight(&self, line: &str, state: State, spans: &mut Vec<Span>) -> State {
+        let text = line.trim_start();
+        let indent = line.len() - text.len();
+        let is_fence = text.starts_with("```") || text.starts_with("~~~");
+        if is_fence || state == State::Fenced {
+            push(spans, 0, line.len(), Style::Code);
+            return match (is_fence, state) {
+                (true, State::Fenced) => State::Normal,
+                _ => State::Fenced,
+            };
+        }
+
+        if text.starts_with('#') {
+            push(spans, 0, line.len(), Style::Heading);
+        } else if text.starts_with('>') {
+            push(spans, 0, line.len(), Style::Comment);
+        } else {
+            let marker = Self::list_marker(text);
+            push(spans, indent, indent + marker, Style::Keyword);
+            Self::inline(line, indent + marker, spans);
+        }
+        State::Normal
+    }
+}
+
+pub struct GitCommit;
+
+impl GitCommit {
+    // display width the subject line and the body lines should stay within
+    const SUBJECT_WIDTH: usize = 50;
+    const BODY_WIDTH: usize = 72;
+    const SCISSORS: &'static str = "# ------------------------ >8 ------------------------";
+}
+
+impl Highlighter for GitCommit {
+    fn name(&self) -> &'static str {
+        "Git commit"
+    }
+
+    fn highlight(&self, line: &str, state: State, spans: &mut Vec<Span>) -> State {
+        // comment lines are dropped by git, the subject is the first line that is left
+        if state == State::Scissors || line == Self::SCISSORS {
+            push(spans, 0, line.len(), Style::Comment);
+            return State::Scissors;
+        }
+        if line.starts_with('#') {
+            push(spans, 0, line.len(), Style::Comment);
+            return state;
+        }
+        if state == State::Normal && line.trim().is_empty() {
+            return State::Normal;
+        }
+
+        // the text past the ruler is marked as too long
+        let ruler = if state == State::Normal {
+            Self::SUBJECT_WIDTH
+        } else {
+            Self::BODY_WIDTH
+        };
+        let mut x = 0;
+        for (i, char) in line.char_indices() {
+            x += char.width().unwrap_or(0);
+            if x > ruler {
+                push(spans, i, line.len(), Style::Overflow);
+                break;
+            }
+        }
+        State::Body
+    }
+}
+
+pub struct GitRebase;
+
+impl Highlighter for GitRebase {
+    fn name(&self) -> &'static str {
+        "Git rebase"
+    }
+
+    fn highlight(&self, line: &str, _: State, spans: &mut Vec<Span>) -> State {
+        // `command commit subject` lines, the command is a keyword and the commit a number
+        let text = line.trim_start();
+        let indent = line.len() - text.len();
+        if text.starts_with('#') {
+            push(spans, 0, line.len(), Style::Comment);
+            return State::Normal;
+        }
+        let command_end = text.find(' ').unwrap_or(text.len());
+        push(spans, indent, indent + command_end, Style::Keyword);
+        let rest = &text[command_end..];
+        let commit = rest.trim_start();
+        let start = indent + command_end + rest.len() - commit.len();
+        let end = start + commit.find(' ').unwrap_or(commit.len());
+        if line[start..end]
+            .bytes()
+            .all(|byte| byte.is_ascii_hexdigit())
+        {
+            push(spans, start, end, Style::Number);
+        }
+        State::Normal
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn styles(highlighter: &dyn Highlighter, lines: &[&str]) -> Vec<Vec<(usize, usize, Style)>> {
+        let mut state = State::Normal;
+        lines
+            .iter()
+            .map(|line| {
+                let mut spans = vec![];
+                state = highlighter.highlight(line, state, &mut spans);
+                spans
+                    .iter()
+                    .map(|span| (span.start, span.end, span.style))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn highlighters_carry_state_across_lines() {
+        assert_eq!(detect("src/main.rs", "").unwrap().name(), "Rust");
+        assert_eq!(
+            detect("run", "#!/usr/bin/env python3").unwrap().name(),
+            "Python"
+        );
+        assert!(detect("notes.txt", "").is_none());
+
+        let rust = detect("a.rs", "").unwrap();
+        assert_eq!(
+            styles(&*rust, &["let c = 'x'; /* a /* b */", "*/ fn f<'a>() {}"]),
+            vec![
+                vec![
+                    (0, 3, Style::Keyword),
+                    (8, 11, Style::String),
+                    (13, 25, Style::Comment)
+                ],
+                vec![(0, 2, Style::Comment), (3, 5, Style::Keyword)],
+            ]
+        );
+
+        let python = detect("a.py", "").unwrap();
+        assert_eq!(
+            styles(&*python, &["x = \"\"\"doc", "end\"\"\" # note"]),
+            vec![
+                vec![(4, 10, Style::String)],
+                vec![(0, 6, Style::String), (7, 13, Style::Comment)]
+            ]
+        );
+
+        let markdown = detect("README.md", "").unwrap();
+        assert_eq!(
+            styles(
+                &*markdown,
+                &["- a `b` snake_case", "```", "# not a heading", "```"]
+            ),
+            vec![
+                vec![(0, 2, Style::Keyword), (4, 7, Style::Code)],
+                vec![(0, 3, Style::Code)],
+                vec![(0, 15, Style::Code)],
+                vec![(0, 3, Style::Code)],
+            ]
+        );
+    }
+
+    #[test]
+    fn git_files_mark_comments_and_long_lines() {
+        let commit = detect(".git/COMMIT_EDITMSG", "").unwrap();
+        let subject = "Fix #12 by moving the cursor before the line it is on";
+        assert_eq!(
+            styles(
+                &*commit,
+                &["", subject, "# Please enter", GitCommit::SCISSORS, "diff"]
+            ),
+            vec![
+                vec![],
+                vec![(50, 53, Style::Overflow)],
+                vec![(0, 14, Style::Comment)],
+                vec![(0, 54, Style::Comment)],
+                vec![(0, 4, Style::Comment)],
+            ]
+        );
+
+        let rebase = detect(".git/rebase-merge/git-rebase-todo", "").unwrap();
+        assert_eq!(
+            styles(&*rebase, &["pick 1a2b3c Add tests", "# p, pick"]),
+            vec![
+                vec![(0, 4, Style::Keyword), (5, 11, Style::Number)],
+                vec![(0, 9, Style::Comment)],
+            ]
+        );
+    }
+}
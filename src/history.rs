@@ -0,0 +1,122 @@
+use ropey::Rope;
+
+pub enum Edit {
+    // a single change to the rope, `index` is a char index
+    Insert { index: usize, text: String },
+    Remove { index: usize, text: String },
+}
+
+impl Edit {
+    pub fn index(&self) -> usize {
+        match self {
+            Edit::Insert { index, .. } | Edit::Remove { index, .. } => *index,
+        }
+    }
+
+    fn apply(&self, text: &mut Rope) {
+        match self {
+            Edit::Insert { index, text: value } => text.insert(*index, value),
+            Edit::Remove { index, text: value } => {
+                text.remove(*index..*index + value.chars().count())
+            }
+        }
+    }
+
+    fn revert(&self, text: &mut Rope) {
+        match self {
+            Edit::Insert { index, text: value } => {
+                text.remove(*index..*index + value.chars().count())
+            }
+            Edit::Remove { index, text: value } => text.insert(*index, value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    // consecutive edits of the same kind are undone together
+    Typing,
+    Deleting,
+    LineEnding,
+    Replace,
+    Other,
+}
+
+struct Step {
+    edits: Vec<Edit>,
+    kind: EditKind,
+    // cursor position as (row, col) before and after the step
+    before: (usize, usize),
+    after: (usize, usize),
+}
+
+impl Step {
+    fn first_index(&self) -> usize {
+        self.edits.iter().map(Edit::index).min().unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    // undo and redo stacks of the edits made to a buffer
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    // whether the last step on the undo stack may still take more edits
+    is_open: bool,
+}
+
+impl History {
+    pub fn record(
+        &mut self,
+        edit: Edit,
+        kind: EditKind,
+        before: (usize, usize),
+        after: (usize, usize),
+    ) {
+        self.redo.clear();
+        if let Some(step) = self.undo.last_mut() {
+            if self.is_open && step.kind == kind {
+                step.edits.push(edit);
+                step.after = after;
+                return;
+            }
+        }
+        self.undo.push(Step {
+            edits: vec![edit],
+            kind,
+            before,
+            after,
+        });
+        self.is_open = true;
+    }
+
+    pub fn seal(&mut self) {
+        // the next edit starts a new undo step
+        self.is_open = false;
+    }
+
+    pub fn undo(&mut self, text: &mut Rope) -> Option<(EditKind, (usize, usize), usize)> {
+        // reverts the last step and returns its kind, the cursor position from before it
+        // and the first char index it changed
+        let step = self.undo.pop()?;
+        for edit in step.edits.iter().rev() {
+            edit.revert(text);
+        }
+        let result = (step.kind, step.before, step.first_index());
+        self.redo.push(step);
+        self.is_open = false;
+        Some(result)
+    }
+
+    pub fn redo(&mut self, text: &mut Rope) -> Option<(EditKind, (usize, usize), usize)> {
+        // applies the last undone step again and returns the same as `undo` with the cursor position from after it
+        let step = self.redo.pop()?;
+        for edit in &step.edits {
+            edit.apply(text);
+        }
+        let result = (step.kind, step.after, step.first_index());
+        self.undo.push(step);
+        self.is_open = false;
+        Some(result)
+    }
+}
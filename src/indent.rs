@@ -0,0 +1,46 @@
+use ropey::Rope;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Indent {
+    Tabs,
+    // number of spaces in one level of indentation
+    Spaces(usize),
+}
+
+impl Indent {
+    pub fn unit(&self) -> String {
+        match self {
+            Indent::Tabs => "\t".to_string(),
+            Indent::Spaces(width) => " ".repeat(*width),
+        }
+    }
+
+    pub fn detect(text: &Rope, tab_width: usize) -> Self {
+        // looks at the indented lines at the start of the file, the smallest space indent is one level
+        // single spaces are ignored since they usually align comments rather than indent
+        let mut tabs = 0;
+        let mut spaces = 0;
+        let mut width = usize::MAX;
+        for line in text.lines().take(1000) {
+            match line.chars().next() {
+                Some('\t') => tabs += 1,
+                Some(' ') => {
+                    let count = line.chars().take_while(|&char| char == ' ').count();
+                    if count > 1 {
+                        spaces += 1;
+                        width = width.min(count);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if tabs > spaces {
+            Indent::Tabs
+        } else if spaces > 0 {
+            Indent::Spaces(width.min(8))
+        } else {
+            Indent::Spaces(tab_width)
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use termion::event::Key;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Modified {
+    // keys pressed with modifiers, termion reports these as unsupported escape sequences
+    Shift(Key),
+    Ctrl(Key),
+    CtrlShift(Key),
+    Alt(Key),
+}
+
+// sequences a terminal in bracketed paste mode sends around pasted text
+pub const PASTE_START: &[u8] = b"\x1b[200~";
+pub const PASTE_END: &[u8] = b"\x1b[201~";
+
+pub fn paste_text(bytes: &[u8]) -> String {
+    // terminals send line breaks in pastes as `\r`, control characters other than tabs are dropped
+    String::from_utf8_lossy(bytes)
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .chars()
+        .filter(|char| !char.is_control() || *char == '\n' || *char == '\t')
+        .collect()
+}
+
+pub fn parse_position(text: &str) -> Option<(usize, usize)> {
+    // parses a 1-based `line` or `line:col` into a 0-based (row, col)
+    let (line, col) = match text.trim().split_once(':') {
+        Some((line, col)) => (line, col.parse::<usize>().ok()?),
+        None => (text.trim(), 1),
+    };
+    let line = line.parse::<usize>().ok()?;
+    Some((line.saturating_sub(1), col.saturating_sub(1)))
+}
+
+pub fn parse_modified(bytes: &[u8]) -> Option<Modified> {
+    // parses xterm style sequences like `ESC [ 1 ; 2 D` (Shift + Left) or `ESC [ 3 ; 5 ~` (Ctrl + Delete)
+    let params = std::str::from_utf8(bytes.strip_prefix(b"\x1b[")?).ok()?;
+    let last = params.chars().last()?;
+    let (code, modifier) = params[..params.len() - 1].split_once(';')?;
+
+    let key = match (code, last) {
+        ("1", 'A') => Key::Up,
+        ("1", 'B') => Key::Down,
+        ("1", 'C') => Key::Right,
+        ("1", 'D') => Key::Left,
+        ("1", 'H') | ("1", '~') | ("7", '~') => Key::Home,
+        ("1", 'F') | ("4", '~') | ("8", '~') => Key::End,
+        ("3", '~') => Key::Delete,
+        ("5", '~') => Key::PageUp,
+        ("6", '~') => Key::PageDown,
+        _ => return None,
+    };
+
+    // the modifier parameter is 1 + a bit mask of shift (1), alt (2) and ctrl (4)
+    match modifier.parse::<u8>().ok()?.checked_sub(1)? {
+        1 => Some(Modified::Shift(key)),
+        2 => Some(Modified::Alt(key)),
+        4 => Some(Modified::Ctrl(key)),
+        5 => Some(Modified::CtrlShift(key)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_modified_keys() {
+        assert_eq!(
+            parse_modified(b"\x1b[1;2D"),
+            Some(Modified::Shift(Key::Left))
+        );
+        assert_eq!(
+            parse_modified(b"\x1b[1;5C"),
+            Some(Modified::Ctrl(Key::Right))
+        );
+        assert_eq!(
+            parse_modified(b"\x1b[3;5~"),
+            Some(Modified::Ctrl(Key::Delete))
+        );
+        assert_eq!(
+            parse_modified(b"\x1b[1;6H"),
+            Some(Modified::CtrlShift(Key::Home))
+        );
+        assert_eq!(parse_modified(b"\x1b[200~"), None);
+    }
+
+    #[test]
+    fn parses_positions() {
+        assert_eq!(parse_position("120"), Some((119, 0)));
+        assert_eq!(parse_position(" 3:7"), Some((2, 6)));
+        assert_eq!(parse_position("3:"), None);
+        assert_eq!(parse_position("x"), None);
+    }
+
+    #[test]
+    fn cleans_pasted_text() {
+        assert_eq!(
+            paste_text(b"fn main() {\r\tok\x07\r\n}"),
+            "fn main() {\n\tok\n}"
+        );
+    }
+}
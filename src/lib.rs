@@ -1,4 +1,16 @@
+pub mod args;
 mod buffer;
+mod clipboard;
+mod command;
 mod cursor;
 pub mod editor;
+mod highlight;
+mod history;
+mod indent;
+mod input;
+mod line;
+mod line_ending;
+pub mod options;
+mod replace;
+mod style;
 mod terminal;
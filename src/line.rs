@@ -0,0 +1,370 @@
+use crate::style::{style_at, Span, Style};
+use ropey::RopeSlice;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+struct Grapheme {
+    // byte range of a grapheme cluster in `Line::value` and the number of terminal cells it takes
+    start: usize,
+    end: usize,
+    width: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct Wrap {
+    // how lines longer than `width` display columns are split into terminal rows
+    pub width: usize,
+    // break rows after whitespace and start continuation rows with a marker
+    pub is_word: bool,
+    // indent continuation rows of word wrapped lines like the line itself
+    pub is_indented: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    // kinds of graphemes that word movement stops between
+    Space,
+    Word,
+    Punctuation,
+}
+
+pub struct Line {
+    // a single line of the buffer without its line ending, split into grapheme clusters
+    // columns used by the buffer are indices into `graphemes`, never byte offsets
+    value: String,
+    graphemes: Vec<Grapheme>,
+}
+
+impl Line {
+    pub fn from(slice: RopeSlice, tab_width: usize) -> Self {
+        let mut len = slice.len_chars();
+        if len > 0 && slice.char(len - 1) == '\n' {
+            len -= 1;
+        }
+        if len > 0 && slice.char(len - 1) == '\r' {
+            len -= 1;
+        }
+        let value = slice.slice(..len).to_string();
+
+        // a tab reaches the next multiple of `tab_width` display columns
+        let mut x = 0;
+        let graphemes = value
+            .grapheme_indices(true)
+            .map(|(start, grapheme)| {
+                let width = if grapheme == "\t" {
+                    tab_width - x % tab_width
+                } else {
+                    grapheme.width().max(1)
+                };
+                x += width;
+                Grapheme {
+                    start,
+                    end: start + grapheme.len(),
+                    width,
+                }
+            })
+            .collect();
+
+        Line { value, graphemes }
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn len(&self) -> usize {
+        // number of grapheme clusters in the line
+        self.graphemes.len()
+    }
+
+    pub fn byte_offset(&self, col: usize) -> usize {
+        // byte offset in the line where the grapheme at `col` starts
+        self.graphemes
+            .get(col)
+            .map_or(self.value.len(), |grapheme| grapheme.start)
+    }
+
+    pub fn class(&self, col: usize, word_chars: &str) -> CharClass {
+        // letters, digits and `word_chars` make up words
+        let char = self.value[self.graphemes[col].start..]
+            .chars()
+            .next()
+            .unwrap_or(' ');
+        if char.is_whitespace() {
+            CharClass::Space
+        } else if char.is_alphanumeric() || word_chars.contains(char) {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+
+    pub fn col_at_byte(&self, byte: usize) -> usize {
+        // number of grapheme clusters that end at or before `byte`
+        self.graphemes
+            .iter()
+            .take_while(|grapheme| grapheme.end <= byte)
+            .count()
+    }
+
+    pub fn x(&self, col: usize) -> usize {
+        // display column where the grapheme at `col` starts
+        self.graphemes[..col.min(self.len())]
+            .iter()
+            .map(|grapheme| grapheme.width)
+            .sum()
+    }
+
+    fn continuation_x(&self, wrap: Wrap) -> usize {
+        // display columns taken by the indentation and marker in front of continuation rows,
+        // at most half of the width so there is always room for the text
+        if !wrap.is_word {
+            return 0;
+        }
+        let indent = if wrap.is_indented {
+            let col = self.value.len() - self.value.trim_start().len();
+            self.x(self.col_at_byte(col))
+        } else {
+            0
+        };
+        (indent + 1).min(wrap.width / 2)
+    }
+
+    fn row_x(&self, row: usize, wrap: Wrap) -> usize {
+        // display column where the text of a wrapped row starts
+        if row == 0 {
+            0
+        } else {
+            self.continuation_x(wrap)
+        }
+    }
+
+    pub fn wrap_starts(&self, wrap: Wrap) -> Vec<usize> {
+        // grapheme index at which each wrapped row starts, a wide grapheme never straddles two rows
+        // word wrapping breaks after the last whitespace that leaves the rest fitting on the next row
+        let continuation_x = self.continuation_x(wrap);
+        let mut starts = vec![0];
+        let mut row_width = 0;
+        // start and width of the word after the last whitespace in the row
+        let mut word_start = None;
+        let mut word_width = 0;
+        for (i, grapheme) in self.graphemes.iter().enumerate() {
+            let row_x = if starts.len() == 1 { 0 } else { continuation_x };
+            if row_width > 0 && row_x + row_width + grapheme.width > wrap.width {
+                match word_start {
+                    Some(start) if continuation_x + word_width + grapheme.width <= wrap.width => {
+                        starts.push(start);
+                        row_width = word_width;
+                    }
+                    _ => {
+                        starts.push(i);
+                        row_width = 0;
+                    }
+                }
+                word_start = None;
+            }
+            row_width += grapheme.width;
+            word_width += grapheme.width;
+            if wrap.is_word && self.value[grapheme.start..grapheme.end].trim().is_empty() {
+                word_start = Some(i + 1);
+                word_width = 0;
+            }
+        }
+        starts
+    }
+
+    pub fn display_rows(&self, wrap: Option<Wrap>) -> usize {
+        match wrap {
+            Some(wrap) => self.wrap_starts(wrap).len(),
+            None => 1,
+        }
+    }
+
+    pub fn position(&self, col: usize, wrap: Option<Wrap>) -> (usize, usize) {
+        // maps a column to the (wrapped row, display column in that row) it is drawn at
+        let Some(wrap) = wrap else {
+            return (0, self.x(col));
+        };
+        let starts = self.wrap_starts(wrap);
+        let row = starts.iter().rposition(|&start| start <= col).unwrap_or(0);
+        (
+            row,
+            self.row_x(row, wrap) + self.x(col) - self.x(starts[row]),
+        )
+    }
+
+    pub fn col_at(&self, row: usize, x: usize, wrap: Option<Wrap>) -> usize {
+        // inverse of `position`, snaps to the start of a wide grapheme and to the end of short rows
+        let (start, end, is_last_row, x) = if let Some(wrap) = wrap {
+            let starts = self.wrap_starts(wrap);
+            let row = row.min(starts.len() - 1);
+            let x = x.saturating_sub(self.row_x(row, wrap));
+            match starts.get(row + 1) {
+                Some(&end) => (starts[row], end, false, x),
+                None => (starts[row], self.len(), true, x),
+            }
+        } else {
+            (0, self.len(), true, x)
+        };
+
+        let mut row_x = 0;
+        for col in start..end {
+            row_x += self.graphemes[col].width;
+            if row_x > x {
+                return col;
+            }
+        }
+
+        if is_last_row {
+            end
+        } else {
+            end - 1
+        }
+    }
+
+    pub fn render(&self, wrap: Wrap, gutter: &str, spans: &[Span], rows: Range<usize>) {
+        // renders the wrapped rows `rows` of the content of `self.value`, a line taller than the
+        // screen is only drawn in part, the gutter is drawn before the first row and continuation rows leave it blank
+        let starts = self.wrap_starts(wrap);
+        for (i, &start) in starts.iter().enumerate().take(rows.end).skip(rows.start) {
+            let end = starts.get(i + 1).copied().unwrap_or(self.len());
+            if i == 0 {
+                Self::render_gutter(gutter);
+            } else {
+                print!("{}", " ".repeat(gutter.len()));
+                let row_x = self.row_x(i, wrap);
+                if row_x > 0 {
+                    Self::render_gutter(&format!("{}↪", " ".repeat(row_x - 1)));
+                }
+            }
+            let mut style = Style::Normal;
+            for grapheme in &self.graphemes[start..end] {
+                style = self.render_grapheme(grapheme, spans, style);
+            }
+            Self::end_row(style);
+        }
+    }
+
+    pub fn render_no_wrap(&self, left_offset: usize, width: usize, gutter: &str, spans: &[Span]) {
+        // renders the display columns starting at `left_offset` that fit in `width`,
+        // the first and last column show a marker instead when the line is cut off there
+        Self::render_gutter(gutter);
+        let line_width = self.x(self.len());
+        let is_cut_left = left_offset > 0 && line_width > 0;
+        let is_cut_right = line_width > left_offset + width;
+        let left_offset = left_offset + usize::from(is_cut_left);
+        let right_offset =
+            left_offset + width - usize::from(is_cut_left) - usize::from(is_cut_right);
+        if is_cut_left {
+            Self::render_gutter("‹");
+        }
+
+        let mut x = 0;
+        let mut style = Style::Normal;
+        for grapheme in &self.graphemes {
+            let next_x = x + grapheme.width;
+            if next_x > right_offset {
+                break;
+            }
+            if x >= left_offset {
+                style = self.render_grapheme(grapheme, spans, style);
+            } else if next_x > left_offset {
+                // a wide grapheme cut by the left edge, fill the visible half
+                print!("{}", " ".repeat(next_x - left_offset));
+            }
+            x = next_x;
+        }
+        if is_cut_right {
+            Style::Normal.render();
+            style = Style::Normal;
+            print!("{}", " ".repeat(right_offset - x.max(left_offset)));
+            Self::render_gutter("›");
+        }
+        Self::end_row(style);
+    }
+
+    fn render_gutter(gutter: &str) {
+        if !gutter.is_empty() {
+            Style::LineNumber.render();
+            print!("{}", gutter);
+            Style::Normal.render();
+        }
+    }
+
+    fn render_grapheme(&self, grapheme: &Grapheme, spans: &[Span], current: Style) -> Style {
+        // prints a grapheme, switching terminal colors only when the style changes
+        let style = style_at(spans, grapheme.start);
+        if style != current {
+            style.render();
+        }
+        match &self.value[grapheme.start..grapheme.end] {
+            "\t" => print!("{}", " ".repeat(grapheme.width)),
+            value => print!("{}", value),
+        }
+        style
+    }
+
+    fn end_row(style: Style) {
+        if style != Style::Normal {
+            Style::Normal.render();
+        }
+        print!("\r\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ropey::Rope;
+
+    use super::*;
+
+    fn chars(width: usize) -> Wrap {
+        Wrap {
+            width,
+            is_word: false,
+            is_indented: false,
+        }
+    }
+
+    #[test]
+    fn line_maps_columns_to_display_width() {
+        let rope = Rope::from_str("e\u{301}日本a\r\n");
+        let line = Line::from(rope.line(0), 4);
+
+        assert_eq!(line.len(), 4);
+        assert_eq!(line.x(1), 1);
+        assert_eq!(line.x(2), 3);
+        assert_eq!(line.x(4), 6);
+        assert_eq!(line.col_at(0, 2, None), 1);
+        assert_eq!(line.col_at(0, 10, None), 4);
+        assert_eq!(line.byte_offset(1), 3);
+        assert_eq!(line.col_at_byte(3), 1);
+        assert_eq!(line.wrap_starts(chars(4)), vec![0, 2]);
+        assert_eq!(line.wrap_starts(chars(2)), vec![0, 1, 2, 3]);
+
+        let rope = Rope::from_str("a\tb\t\tc");
+        let line = Line::from(rope.line(0), 4);
+        assert_eq!(line.x(2), 4);
+        assert_eq!(line.x(4), 8);
+        assert_eq!(line.col_at(0, 2, None), 1);
+        assert_eq!(line.x(line.len()), 13);
+    }
+
+    #[test]
+    fn line_wraps_at_word_boundaries() {
+        let rope = Rope::from_str("  one two three");
+        let line = Line::from(rope.line(0), 4);
+        let wrap = Wrap {
+            width: 8,
+            is_word: true,
+            is_indented: true,
+        };
+
+        assert_eq!(line.wrap_starts(wrap), vec![0, 6, 10]);
+        assert_eq!(line.position(7, Some(wrap)), (1, 4));
+        assert_eq!(line.col_at(1, 4, Some(wrap)), 7);
+        assert_eq!(line.col_at(2, 0, Some(wrap)), 10);
+        assert_eq!(line.wrap_starts(chars(8)), vec![0, 8]);
+    }
+}
@@ -0,0 +1,67 @@
+use ropey::Rope;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        if cfg!(windows) {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+
+    pub fn detect(text: &Rope) -> (Self, bool) {
+        // returns the most used line ending in `text` and whether both kinds are used
+        let mut lf = 0;
+        let mut crlf = 0;
+        for line in text.lines() {
+            match Self::of(line) {
+                Some(LineEnding::Lf) => lf += 1,
+                Some(LineEnding::Crlf) => crlf += 1,
+                None => {}
+            }
+        }
+
+        let line_ending = if lf == 0 && crlf == 0 {
+            LineEnding::default()
+        } else if crlf > lf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        };
+        (line_ending, lf > 0 && crlf > 0)
+    }
+
+    pub fn of(line: ropey::RopeSlice) -> Option<Self> {
+        // the line ending a single line of a rope finishes with, the last line has none
+        let len = line.len_chars();
+        if len == 0 || line.char(len - 1) != '\n' {
+            None
+        } else if len > 1 && line.char(len - 2) == '\r' {
+            Some(LineEnding::Crlf)
+        } else {
+            Some(LineEnding::Lf)
+        }
+    }
+}
@@ -1,5 +1,66 @@
+use std::{env, io, process};
+
+use text_editor::args::{self, Action, USAGE};
 use text_editor::editor::Editor;
+use text_editor::options::{LineNumbers, Options};
 
 fn main() {
-    Editor::default().run();
+    let args = match args::parse(env::args().skip(1)) {
+        Ok(Action::Edit(args)) => args,
+        Ok(Action::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(Action::Version) => {
+            println!("te {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(err) => {
+            eprintln!("te: {}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let defaults = Options::default();
+    let options = Options {
+        readonly: args.readonly,
+        wrap: args.wrap.unwrap_or(defaults.wrap),
+        auto_indent: env::var_os("TE_NO_AUTO_INDENT").is_none(),
+        smart_indent: env::var_os("TE_NO_SMART_INDENT").is_none(),
+        wrap_indent: env::var_os("TE_NO_WRAP_INDENT").is_none(),
+        backup: env::var_os("TE_BACKUP").is_some(),
+        tab_width: env::var("TE_TAB_WIDTH")
+            .ok()
+            .and_then(|width| width.parse().ok())
+            .filter(|&width| width > 0)
+            .unwrap_or(defaults.tab_width),
+        word_chars: env::var("TE_WORD_CHARS").unwrap_or(defaults.word_chars.clone()),
+        side_margin: env::var("TE_SIDE_MARGIN")
+            .ok()
+            .and_then(|margin| margin.parse().ok())
+            .unwrap_or(defaults.side_margin),
+        line_numbers: env::var("TE_LINE_NUMBERS")
+            .ok()
+            .and_then(|name| LineNumbers::from_name(&name))
+            .unwrap_or(defaults.line_numbers),
+    };
+
+    let mut editor = Editor::new(options);
+    if args.wait {
+        editor.wait();
+    }
+    for (filename, position) in &args.files {
+        let result = if filename == "-" {
+            io::read_to_string(io::stdin()).map(|text| editor.open_scratch(&text))
+        } else {
+            editor.open(filename, *position)
+        };
+        if let Err(err) = result {
+            eprintln!("te: could not open {}: {}", filename, err);
+            process::exit(1);
+        }
+    }
+    if !editor.run() && args.wait {
+        process::exit(1);
+    }
 }
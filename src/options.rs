@@ -0,0 +1,117 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    // distance from the cursor line
+    Relative,
+    // absolute on the cursor line, relative on the others
+    Hybrid,
+}
+
+impl LineNumbers {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(LineNumbers::Off),
+            "absolute" => Some(LineNumbers::Absolute),
+            "relative" => Some(LineNumbers::Relative),
+            "hybrid" => Some(LineNumbers::Hybrid),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode {
+    Off,
+    // break lines at any character
+    Char,
+    // break lines at whitespace
+    Word,
+}
+
+// settings that can be changed with the `set` command, `tabwidth=` takes a number
+pub const SETTINGS: &[&str] = &[
+    "autoindent",
+    "linenumbers=absolute",
+    "linenumbers=hybrid",
+    "linenumbers=off",
+    "linenumbers=relative",
+    "noautoindent",
+    "nosmartindent",
+    "nowrap",
+    "smartindent",
+    "tabwidth=",
+    "wrap",
+    "wrap=char",
+    "wrap=word",
+];
+
+#[derive(Clone)]
+pub struct Options {
+    // settings the editor applies to every buffer it opens
+    // keep a copy of the previous file contents as `<file>~` when saving
+    pub backup: bool,
+    // number of display columns between tab stops
+    pub tab_width: usize,
+    // start new lines at the indentation of the line they are split from
+    pub auto_indent: bool,
+    // indent one more level after a line ending with `{`, `(`, `[` or `:`
+    pub smart_indent: bool,
+    pub line_numbers: LineNumbers,
+    pub wrap: WrapMode,
+    // indent continuation rows of word wrapped lines like the line they belong to
+    pub wrap_indent: bool,
+    // columns kept visible on either side of the cursor when scrolling long lines horizontally
+    pub side_margin: usize,
+    // characters besides letters and digits that are part of words for word movement
+    pub word_chars: String,
+    // open buffers without allowing edits
+    pub readonly: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            backup: false,
+            tab_width: 4,
+            auto_indent: true,
+            smart_indent: true,
+            line_numbers: LineNumbers::Off,
+            wrap: WrapMode::Off,
+            wrap_indent: true,
+            side_margin: 5,
+            word_chars: "_".to_string(),
+            readonly: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn set(&mut self, setting: &str) -> Result<(), String> {
+        // applies one of `SETTINGS`
+        match setting {
+            "wrap" | "wrap=char" => self.wrap = WrapMode::Char,
+            "wrap=word" => self.wrap = WrapMode::Word,
+            "nowrap" => self.wrap = WrapMode::Off,
+            "autoindent" => self.auto_indent = true,
+            "noautoindent" => self.auto_indent = false,
+            "smartindent" => self.smart_indent = true,
+            "nosmartindent" => self.smart_indent = false,
+            _ => match setting.split_once('=') {
+                Some(("tabwidth", width)) => {
+                    self.tab_width = width
+                        .parse()
+                        .ok()
+                        .filter(|&width| width > 0)
+                        .ok_or(format!("invalid tab width {}", width))?;
+                }
+                Some(("linenumbers", name)) => {
+                    self.line_numbers = LineNumbers::from_name(name)
+                        .ok_or(format!("invalid line numbers {}", name))?;
+                }
+                _ => return Err(format!("unknown setting {}", setting)),
+            },
+        }
+        Ok(())
+    }
+}
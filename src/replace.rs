@@ -0,0 +1,88 @@
+use regex::{Regex, RegexBuilder};
+
+pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    // `^` and `$` match at the start and end of every line, also before a `\r\n` line ending
+    RegexBuilder::new(pattern)
+        .multi_line(true)
+        .crlf(true)
+        .build()
+}
+
+pub struct Replace {
+    // state of an interactive find-and-replace over a char range of a buffer
+    pub regex: Regex,
+    pub replacement: String,
+    // copy of the buffer text the regex runs on, copied once per session and kept in step with the replacements
+    text: String,
+    // char range the replacements are limited to, it grows and shrinks with the replacements
+    pub scope_start: usize,
+    pub scope_end: usize,
+    // the search starts at `origin`, runs to `scope_end` and wraps around to stop at `origin`
+    pub origin: usize,
+    pub is_wrapped: bool,
+    // char index the next match is searched from
+    pub next: usize,
+    // char range of the match waiting for confirmation
+    pub current: Option<(usize, usize)>,
+    pub count: usize,
+}
+
+impl Replace {
+    pub fn new(
+        regex: Regex,
+        replacement: String,
+        text: String,
+        scope: (usize, usize),
+        origin: usize,
+    ) -> Self {
+        let origin = origin.clamp(scope.0, scope.1);
+        Replace {
+            regex,
+            replacement,
+            text,
+            scope_start: scope.0,
+            scope_end: scope.1,
+            origin,
+            is_wrapped: false,
+            next: origin,
+            current: None,
+            count: 0,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        // char index the current pass of the search stops at
+        if self.is_wrapped {
+            self.origin
+        } else {
+            self.scope_end
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn expand(&self, start: usize) -> Option<(usize, usize, String)> {
+        // finds the match at or after byte `start` of the text and returns its byte range
+        // with the replacement, capture groups like `$1` or `${name}` expanded
+        let captures = self.regex.captures_at(&self.text, start)?;
+        let found = captures.get(0)?;
+        let mut replacement = String::new();
+        captures.expand(&self.replacement, &mut replacement);
+        Some((found.start(), found.end(), replacement))
+    }
+
+    pub fn splice(&mut self, start: usize, end: usize, replacement: &str) {
+        // applies a replacement of the byte range `start..end` to the copy of the text
+        self.text.replace_range(start..end, replacement);
+    }
+
+    pub fn shift(&mut self, removed: usize, inserted: usize) {
+        // keeps the scope in place after a replacement changed the text length
+        self.scope_end = self.scope_end + inserted - removed;
+        if self.is_wrapped {
+            self.origin = self.origin + inserted - removed;
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use termion::color;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Style {
+    // how a piece of text is colored on the terminal
+    Normal,
+    Match,
+    CurrentMatch,
+    Selection,
+    LineNumber,
+    // syntax highlighting, only the foreground is colored
+    Keyword,
+    Type,
+    Key,
+    String,
+    Comment,
+    Number,
+    Constant,
+    Heading,
+    Code,
+    Emphasis,
+    // text past the length a line should stay within
+    Overflow,
+}
+
+impl Style {
+    pub fn render(&self) {
+        match self {
+            Style::Normal => print!("{}{}", color::Bg(color::Reset), color::Fg(color::Reset)),
+            Style::Match => print!("{}{}", color::Bg(color::Yellow), color::Fg(color::Black)),
+            Style::CurrentMatch => {
+                print!("{}{}", color::Bg(color::LightRed), color::Fg(color::Black))
+            }
+            Style::Selection => print!("{}{}", color::Bg(color::Blue), color::Fg(color::White)),
+            Style::LineNumber => Self::foreground(color::LightBlack),
+            Style::Keyword => Self::foreground(color::Magenta),
+            Style::Type => Self::foreground(color::Cyan),
+            Style::Key => Self::foreground(color::Blue),
+            Style::String => Self::foreground(color::Green),
+            Style::Comment => Self::foreground(color::LightBlack),
+            Style::Number => Self::foreground(color::Yellow),
+            Style::Constant => Self::foreground(color::LightRed),
+            Style::Heading => Self::foreground(color::LightCyan),
+            Style::Code => Self::foreground(color::LightGreen),
+            Style::Emphasis => Self::foreground(color::LightYellow),
+            Style::Overflow => Self::foreground(color::Red),
+        }
+    }
+
+    fn foreground(fg: impl color::Color) {
+        print!("{}{}", color::Bg(color::Reset), color::Fg(fg));
+    }
+}
+
+pub struct Span {
+    // a byte range of a line drawn with `style`, later spans take precedence over earlier ones
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+pub fn style_at(spans: &[Span], byte: usize) -> Style {
+    spans
+        .iter()
+        .rev()
+        .find(|span| span.start <= byte && byte < span.end)
+        .map_or(Style::Normal, |span| span.style)
+}
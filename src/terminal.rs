@@ -1,11 +1,12 @@
 pub struct Terminal;
 
 impl Terminal {
+    // falls back to a conventional 80x24 window when stdout is not a terminal (e.g. in tests)
     pub fn rows() -> usize {
-        termion::terminal_size().unwrap().1 as usize
+        termion::terminal_size().map_or(24, |(_, rows)| rows) as usize
     }
 
     pub fn cols() -> usize {
-        termion::terminal_size().unwrap().0 as usize
+        termion::terminal_size().map_or(80, |(cols, _)| cols) as usize
     }
 }